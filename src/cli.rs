@@ -0,0 +1,887 @@
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use csv;
+use de;
+use gtfs::{self, ExportOptions};
+use model::{Date, Day, DayTime, Point, Route, Schedule, Stop, Timestamp};
+use osm;
+use search::{
+    ConnectionScan, Engine, FootpathOptions, Locator, Raptor, SearchOptions, Searcher, Service,
+};
+use serde_json;
+use server;
+use std::fs;
+use std::io;
+use std::path::Path;
+use Res;
+
+pub fn app() -> App<'static, 'static> {
+    App::new("route-search")
+        .about("Public transport route planner")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .arg(
+            Arg::with_name("log-level")
+                .long("log-level")
+                .global(true)
+                .takes_value(true)
+                .default_value("info")
+                .possible_values(&["off", "error", "warn", "info", "debug", "trace"]),
+        )
+        .arg(
+            Arg::with_name("data")
+                .long("data")
+                .global(true)
+                .takes_value(true)
+                .default_value("data")
+                .help("Directory containing stops.json and schedules.json"),
+        )
+        .arg(
+            Arg::with_name("gtfs")
+                .long("gtfs")
+                .global(true)
+                .takes_value(true)
+                .help("GTFS feed (zip or directory) to load instead of --data"),
+        )
+        .arg(
+            Arg::with_name("transfers")
+                .long("transfers")
+                .global(true)
+                .takes_value(true)
+                .help("GTFS transfers.txt with walks between stops to add or forbid"),
+        )
+        .arg(
+            Arg::with_name("osm")
+                .long("osm")
+                .global(true)
+                .takes_value(true)
+                .help("OpenStreetMap XML extract to walk along streets instead of straight lines"),
+        )
+        .arg(
+            Arg::with_name("footpath-radius")
+                .long("footpath-radius")
+                .global(true)
+                .takes_value(true)
+                .default_value("1000")
+                .help("Longest walk between stops, in meters"),
+        )
+        .arg(
+            Arg::with_name("calendar")
+                .long("calendar")
+                .global(true)
+                .takes_value(true)
+                .help("Service calendar with holidays, used together with --date"),
+        )
+        .arg(
+            Arg::with_name("date")
+                .long("date")
+                .global(true)
+                .takes_value(true)
+                .help("Build service for the week starting at this date, YYYY-MM-DD"),
+        )
+        .subcommand(
+            SubCommand::with_name("route")
+                .about("Finds a route between two places")
+                .args(&endpoint_args())
+                .args(&departure_args())
+                .arg(
+                    Arg::with_name("arrive-by")
+                        .long("arrive-by")
+                        .help("Treat the time as latest arrival instead of departure"),
+                )
+                .arg(
+                    Arg::with_name("pareto")
+                        .long("pareto")
+                        .conflicts_with("arrive-by")
+                        .help("Show all routes trading off arrival, transfers and walking"),
+                )
+                .arg(
+                    Arg::with_name("alternatives")
+                        .long("alternatives")
+                        .takes_value(true)
+                        .conflicts_with_all(&["arrive-by", "pareto"])
+                        .help("Show up to this many routes using different lines"),
+                )
+                .arg(engine_arg(&["dijkstra", "raptor", "csa"]))
+                .args(&option_args())
+                .arg(format_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("profile")
+                .about("Finds all useful journeys departing within a time window")
+                .args(&endpoint_args())
+                .args(&departure_args())
+                .arg(
+                    Arg::with_name("until")
+                        .long("until")
+                        .takes_value(true)
+                        .required(true)
+                        .help("Latest departure time as HH:MM"),
+                )
+                .arg(engine_arg(&["dijkstra", "csa"]))
+                .args(&option_args())
+                .arg(format_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("isochrone")
+                .about("Finds stops and area reachable within a time budget")
+                .arg(origin_arg())
+                .args(&departure_args())
+                .arg(
+                    Arg::with_name("minutes")
+                        .long("minutes")
+                        .takes_value(true)
+                        .required(true)
+                        .help("Time budget in minutes"),
+                )
+                .args(&option_args())
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .takes_value(true)
+                        .default_value("text")
+                        .possible_values(&["text", "geojson"]),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("matrix")
+                .about("Computes travel times between many origins and destinations")
+                .arg(
+                    Arg::with_name("origins")
+                        .long("origins")
+                        .takes_value(true)
+                        .required(true)
+                        .help("CSV file of places with id, lat and lng columns"),
+                )
+                .arg(
+                    Arg::with_name("destinations")
+                        .long("destinations")
+                        .takes_value(true)
+                        .help("CSV file like --origins, defaults to the origins"),
+                )
+                .args(&departure_args())
+                .args(&option_args())
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .takes_value(true)
+                        .default_value("csv")
+                        .possible_values(&["csv", "json"]),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("departures")
+                .about("Shows the next departures from a stop")
+                .arg(
+                    Arg::with_name("stop")
+                        .long("stop")
+                        .takes_value(true)
+                        .required(true)
+                        .help("Stop id, or name of all its platforms"),
+                )
+                .args(&departure_args())
+                .arg(
+                    Arg::with_name("count")
+                        .long("count")
+                        .takes_value(true)
+                        .default_value("10")
+                        .help("How many departures to show"),
+                )
+                .arg(format_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("line")
+                .about("Shows stops of a line's tracks, or a single trip with times")
+                .arg(
+                    Arg::with_name("line")
+                        .long("line")
+                        .takes_value(true)
+                        .required(true)
+                        .help("Line id or name, e.g. 3G"),
+                )
+                .arg(
+                    Arg::with_name("track")
+                        .long("track")
+                        .takes_value(true)
+                        .requires("time")
+                        .help("Track name, to show its first trip after --time"),
+                )
+                .arg(
+                    Arg::with_name("day")
+                        .long("day")
+                        .takes_value(true)
+                        .help("Day of week, e.g. Tue, defaults to the day of --date"),
+                )
+                .arg(
+                    Arg::with_name("time")
+                        .long("time")
+                        .takes_value(true)
+                        .requires("track")
+                        .help("Departure time from the first stop as HH:MM"),
+                )
+                .arg(format_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("stops")
+                .about("Finds stops by name, ignoring diacritics and typos")
+                .arg(
+                    Arg::with_name("name")
+                        .long("name")
+                        .takes_value(true)
+                        .required(true)
+                        .help("Name or its beginning, e.g. zaliasis"),
+                )
+                .arg(
+                    Arg::with_name("limit")
+                        .long("limit")
+                        .takes_value(true)
+                        .default_value("10")
+                        .help("How many names to show"),
+                )
+                .arg(format_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("export")
+                .about("Writes the loaded network as a GTFS feed")
+                .arg(
+                    Arg::with_name("out")
+                        .long("out")
+                        .takes_value(true)
+                        .required(true)
+                        .help("Directory to write the feed to, or a .zip file"),
+                )
+                .arg(
+                    Arg::with_name("agency-name")
+                        .long("agency-name")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("agency-url")
+                        .long("agency-url")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("agency-timezone")
+                        .long("agency-timezone")
+                        .takes_value(true)
+                        .required(true)
+                        .help("Time zone of the schedules, e.g. Europe/Vilnius"),
+                )
+                .arg(
+                    Arg::with_name("start-date")
+                        .long("start-date")
+                        .takes_value(true)
+                        .requires("end-date")
+                        .help("First service day, YYYY-MM-DD [default: earliest timetable date]"),
+                )
+                .arg(
+                    Arg::with_name("end-date")
+                        .long("end-date")
+                        .takes_value(true)
+                        .requires("start-date")
+                        .help("Last service day, YYYY-MM-DD [default: latest timetable date]"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("serve")
+                .about("Serves route queries over HTTP")
+                .arg(
+                    Arg::with_name("addr")
+                        .long("addr")
+                        .takes_value(true)
+                        .default_value("127.0.0.1:8080"),
+                )
+                .arg(
+                    Arg::with_name("threads")
+                        .long("threads")
+                        .takes_value(true)
+                        .default_value("4"),
+                ),
+        )
+}
+
+fn origin_arg() -> Arg<'static, 'static> {
+    Arg::with_name("from")
+        .long("from")
+        .takes_value(true)
+        .required(true)
+        .help("Origin as lat,lng or a stop id or name")
+}
+
+fn endpoint_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        origin_arg(),
+        Arg::with_name("to")
+            .long("to")
+            .takes_value(true)
+            .required(true)
+            .help("Destination as lat,lng or a stop id or name"),
+    ]
+}
+
+fn format_arg() -> Arg<'static, 'static> {
+    Arg::with_name("format")
+        .long("format")
+        .takes_value(true)
+        .default_value("text")
+        .possible_values(&["text", "json"])
+}
+
+fn engine_arg(engines: &'static [&'static str]) -> Arg<'static, 'static> {
+    Arg::with_name("engine")
+        .long("engine")
+        .takes_value(true)
+        .default_value("dijkstra")
+        .possible_values(engines)
+        .help("Search algorithm, only used for plain queries")
+}
+
+fn departure_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("day")
+            .long("day")
+            .takes_value(true)
+            .required_unless("date")
+            .help("Day of week, e.g. Tue, defaults to the day of --date"),
+        Arg::with_name("time")
+            .long("time")
+            .takes_value(true)
+            .required(true)
+            .help("Departure (or arrival) time as HH:MM"),
+    ]
+}
+
+const TRANSPORT_TYPES: &[&str] = &["trolley", "bus", "express", "night_bus"];
+
+fn option_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("walk-speed")
+            .long("walk-speed")
+            .takes_value(true)
+            .help("Walking speed in km/h [default: 4]"),
+        Arg::with_name("max-walk")
+            .long("max-walk")
+            .takes_value(true)
+            .help("Longest single walk in meters [default: 500]"),
+        Arg::with_name("max-total-walk")
+            .long("max-total-walk")
+            .takes_value(true)
+            .help("Longest total walk in meters [default: 2000]"),
+        Arg::with_name("transfer-time")
+            .long("transfer-time")
+            .takes_value(true)
+            .help("Seconds needed to board a vehicle [default: 180]"),
+        Arg::with_name("transfer-penalty")
+            .long("transfer-penalty")
+            .takes_value(true)
+            .help("Seconds of travel time a transfer is worth avoiding [default: 60]"),
+        Arg::with_name("max-transfers")
+            .long("max-transfers")
+            .takes_value(true)
+            .help("Most vehicle changes allowed"),
+        Arg::with_name("max-slowdown")
+            .long("max-slowdown")
+            .takes_value(true)
+            .help("With --pareto, drop routes this many times slower than the fastest one"),
+        Arg::with_name("modes")
+            .long("modes")
+            .takes_value(true)
+            .use_delimiter(true)
+            .possible_values(TRANSPORT_TYPES)
+            .help("Only use these transport types"),
+        Arg::with_name("exclude-modes")
+            .long("exclude-modes")
+            .takes_value(true)
+            .use_delimiter(true)
+            .possible_values(TRANSPORT_TYPES)
+            .help("Never use these transport types"),
+        Arg::with_name("ban-lines")
+            .long("ban-lines")
+            .takes_value(true)
+            .use_delimiter(true)
+            .help("Never use these lines, e.g. 1,3G"),
+        Arg::with_name("prefer-lines")
+            .long("prefer-lines")
+            .takes_value(true)
+            .use_delimiter(true)
+            .help("Prefer these lines over others"),
+        Arg::with_name("other-line-penalty")
+            .long("other-line-penalty")
+            .takes_value(true)
+            .help("With --prefer-lines, seconds of travel time worth riding them [default: 300]"),
+    ]
+}
+
+pub fn run(matches: &ArgMatches) -> Res {
+    match matches.subcommand() {
+        ("route", Some(matches)) => route(matches),
+        ("profile", Some(matches)) => profile(matches),
+        ("isochrone", Some(matches)) => isochrone(matches),
+        ("matrix", Some(matches)) => matrix(matches),
+        ("departures", Some(matches)) => departures(matches),
+        ("line", Some(matches)) => line(matches),
+        ("stops", Some(matches)) => stops(matches),
+        ("export", Some(matches)) => export(matches),
+        ("serve", Some(matches)) => serve(matches),
+        _ => unreachable!("subcommand is required"),
+    }
+}
+
+fn route(matches: &ArgMatches) -> Res {
+    let (stops, schedules, footpaths) = load_network(matches)?;
+    let locator = Locator::new(&stops);
+    let from = locate(&locator, value(matches, "from")?)?;
+    let to = locate(&locator, value(matches, "to")?)?;
+    let time = parse_departure(matches)?;
+    let options = parse_options(matches)?;
+    let network = (stops, schedules);
+    let format = value(matches, "format")?;
+
+    if ["pareto", "alternatives", "arrive-by"]
+        .iter()
+        .any(|&flag| matches.is_present(flag))
+    {
+        let searcher = build(matches, network, &footpaths, Searcher::build)?;
+        if matches.is_present("pareto") {
+            let routes = searcher.find_pareto_routes(from, to, time, &options);
+            return print_routes(&routes, format);
+        }
+        if let Some(count) = matches.value_of("alternatives") {
+            let routes = searcher.find_alternatives(from, to, time, count.parse()?, &options);
+            return print_routes(&routes, format);
+        }
+        info!("Starting route search");
+        let route = searcher.find_route_arriving_by(from, to, time, &options);
+        info!("Finished search, got route? {}", route.is_some());
+        return print_route(route, format);
+    }
+
+    let engine: Box<dyn Engine> = match value(matches, "engine")? {
+        "raptor" => Box::new(build(matches, network, &footpaths, Raptor::build)?),
+        "csa" => Box::new(build(matches, network, &footpaths, ConnectionScan::build)?),
+        _ => Box::new(build(matches, network, &footpaths, Searcher::build)?),
+    };
+    info!("Starting route search");
+    let route = engine.find_route(from, to, time, &options);
+    info!("Finished search, got route? {}", route.is_some());
+    print_route(route, format)
+}
+
+fn print_route(route: Option<Route>, format: &str) -> Res {
+    match format {
+        "json" => {
+            println!("{}", serde_json::to_string_pretty(&route)?);
+        }
+        _ => {
+            if let Some(route) = route {
+                println!("Got route");
+                for segment in &route.segments {
+                    println!("{}", segment);
+                }
+            } else {
+                println!("No route found");
+            }
+        }
+    }
+    Ok(())
+}
+
+fn profile(matches: &ArgMatches) -> Res {
+    let (stops, schedules, footpaths) = load_network(matches)?;
+    let locator = Locator::new(&stops);
+    let from = locate(&locator, value(matches, "from")?)?;
+    let to = locate(&locator, value(matches, "to")?)?;
+    let network = (stops, schedules);
+    let earliest = parse_departure(matches)?;
+    let until = value(matches, "until")?.parse::<DayTime>()?;
+    // the window may go past midnight
+    let day = 24 * 3600;
+    let latest = earliest.offset((until.raw + day - earliest.time().raw) % day);
+
+    let options = parse_options(matches)?;
+    let format = value(matches, "format")?;
+    if value(matches, "engine")? == "csa" {
+        let csa = build(matches, network, &footpaths, ConnectionScan::build)?;
+        print_routes(
+            &csa.find_profile(from, to, earliest, latest, &options)?,
+            format,
+        )
+    } else {
+        let searcher = build(matches, network, &footpaths, Searcher::build)?;
+        print_routes(
+            &searcher.find_profile(from, to, earliest, latest, &options),
+            format,
+        )
+    }
+}
+
+fn isochrone(matches: &ArgMatches) -> Res {
+    let searcher = build_searcher(matches)?;
+    let from = value(matches, "from")?;
+    let from = searcher
+        .locate(from)
+        .ok_or_else(|| format!("unknown location: {}", from))?;
+    let departure = parse_departure(matches)?;
+    let budget = value(matches, "minutes")?.parse::<u64>()? * 60;
+    let options = parse_options(matches)?;
+    let isochrone = searcher.find_isochrone(from, departure, budget, &options);
+    match value(matches, "format")? {
+        "geojson" => println!("{}", serde_json::to_string_pretty(&isochrone.geojson())?),
+        _ => {
+            for reached in &isochrone.stops {
+                println!(
+                    "{:>3} min, at {} - {} ({} transfers)",
+                    reached.duration / 60,
+                    reached.arrival,
+                    reached.stop.name,
+                    reached.transfers
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct Place {
+    id: String,
+    lat: f64,
+    lng: f64,
+}
+
+fn read_places(path: &str) -> Res<Vec<Place>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_path(path)?;
+    let mut places = Vec::new();
+    for place in reader.deserialize() {
+        places.push(place?);
+    }
+    Ok(places)
+}
+
+fn matrix(matches: &ArgMatches) -> Res {
+    let searcher = build_searcher(matches)?;
+    let origins = read_places(value(matches, "origins")?)?;
+    let destinations = match matches.value_of("destinations") {
+        Some(path) => read_places(path)?,
+        None => read_places(value(matches, "origins")?)?,
+    };
+    let departure = parse_departure(matches)?;
+    let options = parse_options(matches)?;
+    let points = |places: &[Place]| {
+        places
+            .iter()
+            .map(|place| Point {
+                lat: place.lat,
+                lng: place.lng,
+            })
+            .collect::<Vec<_>>()
+    };
+    info!(
+        "Computing {} x {} travel times",
+        origins.len(),
+        destinations.len()
+    );
+    let durations = searcher.travel_times(
+        &points(&origins),
+        &points(&destinations),
+        departure,
+        &options,
+    );
+
+    match value(matches, "format")? {
+        "json" => {
+            let ids = |places: &[Place]| places.iter().map(|p| p.id.clone()).collect::<Vec<_>>();
+            let json = json!({
+                "origins": ids(&origins),
+                "destinations": ids(&destinations),
+                "durations": durations,
+            });
+            println!("{}", serde_json::to_string_pretty(&json)?);
+        }
+        _ => {
+            let mut writer = csv::Writer::from_writer(io::stdout());
+            writer.write_record(["origin", "destination", "duration"])?;
+            for (origin, row) in origins.iter().zip(&durations) {
+                for (destination, duration) in destinations.iter().zip(row) {
+                    let duration = duration.map(|d| d.to_string()).unwrap_or_default();
+                    writer.write_record([&origin.id, &destination.id, &duration])?;
+                }
+            }
+            writer.flush()?;
+        }
+    }
+    Ok(())
+}
+
+fn departures(matches: &ArgMatches) -> Res {
+    let searcher = build_searcher(matches)?;
+    let stop = value(matches, "stop")?;
+    let time = parse_departure(matches)?;
+    let count = value(matches, "count")?.parse::<usize>()?;
+    let departures = searcher
+        .departures(stop, time, count)
+        .ok_or_else(|| format!("unknown stop: {}", stop))?;
+    match value(matches, "format")? {
+        "json" => println!("{}", serde_json::to_string_pretty(&departures)?),
+        _ => {
+            for departure in &departures {
+                println!(
+                    "{} - {} {} to {}, from {} ({} stops)",
+                    departure.time,
+                    departure.typ,
+                    departure.line,
+                    departure.track,
+                    departure.stop.id,
+                    departure.following.len()
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+fn line(matches: &ArgMatches) -> Res {
+    let searcher = build_searcher(matches)?;
+    let line = value(matches, "line")?;
+    let json = value(matches, "format")? == "json";
+    if let Some(track) = matches.value_of("track") {
+        let departure = parse_departure(matches)?;
+        let trip = searcher
+            .find_trip(line, track, departure)
+            .ok_or_else(|| format!("no trip of line {} track {}", line, track))?;
+        if json {
+            println!("{}", serde_json::to_string_pretty(&trip)?);
+        } else {
+            for stop in &trip.stops {
+                println!("{} - {} ({})", stop.time, stop.stop.name, stop.stop.id);
+            }
+        }
+        return Ok(());
+    }
+
+    let tracks = searcher
+        .line_tracks(line)
+        .ok_or_else(|| format!("unknown line: {}", line))?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&tracks)?);
+    } else {
+        println!(
+            "{} {} - {}",
+            tracks.line.typ, tracks.line.name, tracks.line.long_name
+        );
+        for track in &tracks.tracks {
+            println!("Track {}:", track.name);
+            for stop in &track.stops {
+                println!("  {} ({})", stop.name, stop.id);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn stops(matches: &ArgMatches) -> Res {
+    let searcher = build_searcher(matches)?;
+    let limit = value(matches, "limit")?.parse::<usize>()?;
+    let found = searcher.find_stops(value(matches, "name")?, limit);
+    match value(matches, "format")? {
+        "json" => println!("{}", serde_json::to_string_pretty(&found)?),
+        _ => {
+            for name in &found {
+                println!("{}", name.name);
+                for platform in &name.stops {
+                    println!(
+                        "  {} - {}, towards {}",
+                        platform.stop.id,
+                        platform.area.unwrap_or("?"),
+                        platform.direction.unwrap_or("?")
+                    );
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn export(matches: &ArgMatches) -> Res {
+    let (stops, schedules, _) = load_network(matches)?;
+    let (start_date, end_date) = match matches.value_of("start-date") {
+        Some(start) => (start.parse()?, value(matches, "end-date")?.parse()?),
+        None => gtfs::service_period(&schedules)
+            .ok_or("timetables do not refer to any dates, pass --start-date and --end-date")?,
+    };
+    let options = ExportOptions {
+        agency_name: value(matches, "agency-name")?.to_owned(),
+        agency_url: value(matches, "agency-url")?.to_owned(),
+        agency_timezone: value(matches, "agency-timezone")?.to_owned(),
+        start_date,
+        end_date,
+    };
+    let out = value(matches, "out")?;
+    gtfs::save(out, &stops, &schedules, &options)?;
+    info!(
+        "Wrote {} stops and {} schedules to {}",
+        stops.len(),
+        schedules.len(),
+        out
+    );
+    Ok(())
+}
+
+fn print_routes(routes: &[Route], format: &str) -> Res {
+    match format {
+        "json" => println!("{}", serde_json::to_string_pretty(routes)?),
+        _ => {
+            if routes.is_empty() {
+                println!("No routes found");
+            }
+            for route in routes {
+                println!(
+                    "Leave at {}, arrive at {}, {} transfers, {:.0} m on foot",
+                    route.departure_time,
+                    route.arrival_time,
+                    route.transfers(),
+                    route.walk_distance()
+                );
+                for segment in &route.segments {
+                    println!("    {}", segment);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn value<'a>(matches: &'a ArgMatches, name: &str) -> Res<&'a str> {
+    matches
+        .value_of(name)
+        .ok_or_else(|| format!("missing argument: --{}", name).into())
+}
+
+fn serve(matches: &ArgMatches) -> Res {
+    let searcher = build_searcher(matches)?;
+    let addr = value(matches, "addr")?;
+    let threads = value(matches, "threads")?.parse::<usize>()?;
+    server::serve(searcher, addr, threads)
+}
+
+/// Loads stops and schedules, and decides how to walk to and between stops.
+/// Transfers from `--transfers` take precedence over ones in a GTFS feed.
+fn load_network(matches: &ArgMatches) -> Res<(Vec<Stop>, Vec<Schedule>, FootpathOptions)> {
+    let (stops, schedules, mut transfers) = if let Some(path) = matches.value_of("gtfs") {
+        let feed = gtfs::load(path)?;
+        (feed.stops, feed.schedules, feed.transfers)
+    } else {
+        let dir = Path::new(value(matches, "data")?);
+        let stops = de::stops(&fs::read_to_string(dir.join("stops.json"))?)?;
+        let schedules = de::schedules(&fs::read_to_string(dir.join("schedules.json"))?)?;
+        (stops, schedules, Vec::new())
+    };
+    if let Some(path) = matches.value_of("transfers") {
+        transfers.extend(gtfs::transfers(&fs::read_to_string(path)?)?);
+    }
+    debug!("Loaded {} stops", stops.len());
+    debug!("Loaded {} schedules", schedules.len());
+    let streets = match matches.value_of("osm") {
+        Some(path) => Some(osm::load(path)?),
+        None => None,
+    };
+    let footpaths = FootpathOptions {
+        radius: value(matches, "footpath-radius")?.parse()?,
+        transfers,
+        streets,
+    };
+    Ok((stops, schedules, footpaths))
+}
+
+fn build_searcher(matches: &ArgMatches) -> Res<Searcher> {
+    let (stops, schedules, footpaths) = load_network(matches)?;
+    build(matches, (stops, schedules), &footpaths, Searcher::build)
+}
+
+/// Builds an engine either for a regular week or, if a date is given, for
+/// the week starting at that date with service taken from the calendar.
+fn build<T>(
+    matches: &ArgMatches,
+    (stops, schedules): (Vec<Stop>, Vec<Schedule>),
+    footpaths: &FootpathOptions,
+    engine: fn(Vec<Stop>, Vec<Schedule>, Service, &FootpathOptions) -> T,
+) -> Res<T> {
+    let engine = if let Some(date) = matches.value_of("date") {
+        let week_start = date.parse::<Date>()?;
+        let calendar = match matches.value_of("calendar") {
+            Some(path) => de::calendar(&fs::read_to_string(path)?)?,
+            None => Default::default(),
+        };
+        let service = Service::Dated {
+            calendar: &calendar,
+            week_start,
+        };
+        engine(stops, schedules, service, footpaths)
+    } else {
+        engine(stops, schedules, Service::Weekly, footpaths)
+    };
+    debug!("Built searcher");
+    Ok(engine)
+}
+
+fn parse_departure(matches: &ArgMatches) -> Res<Timestamp> {
+    let time = value(matches, "time")?.parse::<DayTime>()?;
+    match matches.value_of("day") {
+        Some(day) => Ok(Timestamp::new(day.parse::<Day>()?, time)),
+        None => {
+            // dated searchers are built for the week starting at `--date`
+            let date = value(matches, "date")?.parse()?;
+            Ok(Timestamp::on_date(date, time, date).ok_or("date outside of the searched week")?)
+        }
+    }
+}
+
+fn parse_options(matches: &ArgMatches) -> Res<SearchOptions> {
+    let mut options = SearchOptions::default();
+    if let Some(speed) = matches.value_of("walk-speed") {
+        options.walk_speed = speed.parse::<f64>()? * 1000.0 / 3600.0;
+    }
+    if let Some(distance) = matches.value_of("max-walk") {
+        options.max_walk_distance = distance.parse()?;
+    }
+    if let Some(distance) = matches.value_of("max-total-walk") {
+        options.max_total_walk = distance.parse()?;
+    }
+    if let Some(time) = matches.value_of("transfer-time") {
+        options.transfer_time = time.parse()?;
+    }
+    if let Some(penalty) = matches.value_of("transfer-penalty") {
+        options.transfer_penalty = penalty.parse()?;
+    }
+    if let Some(transfers) = matches.value_of("max-transfers") {
+        options.max_transfers = Some(transfers.parse()?);
+    }
+    if let Some(slowdown) = matches.value_of("max-slowdown") {
+        options.max_slowdown = Some(slowdown.parse()?);
+    }
+    if let Some(modes) = matches.values_of("modes") {
+        options.allowed_types = Some(modes.map(str::parse).collect::<Result<_, _>>()?);
+    }
+    if let Some(modes) = matches.values_of("exclude-modes") {
+        options.denied_types = modes.map(str::parse).collect::<Result<_, _>>()?;
+    }
+    if let Some(lines) = matches.values_of("ban-lines") {
+        options.banned_lines = lines.map(str::to_owned).collect();
+    }
+    if let Some(lines) = matches.values_of("prefer-lines") {
+        options.preferred_lines = lines.map(str::to_owned).collect();
+    }
+    if let Some(penalty) = matches.value_of("other-line-penalty") {
+        options.other_line_penalty = penalty.parse()?;
+    }
+    options.validate()?;
+    Ok(options)
+}
+
+fn locate(locator: &Locator, location: &str) -> Res<Point> {
+    locator
+        .locate(location)
+        .ok_or_else(|| format!("unknown location: {}", location).into())
+}
@@ -1,4 +1,4 @@
-use model::{Schedule, Stop};
+use model::{Calendar, Date, Day, Departure, Schedule, Stop};
 use serde_json;
 use Res;
 
@@ -19,6 +19,54 @@ pub fn schedules(json: &str) -> Res<Vec<Schedule>> {
         schedules: Vec<Schedule>,
     }
 
-    let wrapper: Wrapper = serde_json::from_str(json)?;
+    let mut wrapper: Wrapper = serde_json::from_str(json)?;
+    for schedule in &mut wrapper.schedules {
+        let line = &schedule.name;
+        for track in &mut schedule.tracks {
+            let name = &track.name;
+            for timetable in &mut track.timetables {
+                timetable.departures.retain(|departure| match *departure {
+                    Departure::Periodic(ref period) if period.is_empty() => {
+                        warn!(
+                            "Skipping period {}-{} with headway {} of {} {}",
+                            period.from, period.to, period.headway, line, name
+                        );
+                        false
+                    }
+                    _ => true,
+                });
+            }
+        }
+    }
     Ok(wrapper.schedules)
 }
+
+/// Parses a service calendar:
+///
+/// ```json
+/// { "Holidays": [{ "Date": "2018-07-06", "ServiceDay": "Sun" }] }
+/// ```
+pub fn calendar(json: &str) -> Res<Calendar> {
+    #[derive(Deserialize)]
+    struct Holiday {
+        #[serde(rename = "Date")]
+        date: String,
+        #[serde(rename = "ServiceDay")]
+        service_day: String,
+    }
+
+    #[derive(Deserialize)]
+    struct Wrapper {
+        #[serde(rename = "Holidays")]
+        holidays: Vec<Holiday>,
+    }
+
+    let wrapper: Wrapper = serde_json::from_str(json)?;
+    let mut calendar = Calendar::default();
+    for holiday in wrapper.holidays {
+        let date = holiday.date.parse::<Date>()?;
+        let day = holiday.service_day.parse::<Day>()?;
+        calendar.overrides.insert(date, day);
+    }
+    Ok(calendar)
+}
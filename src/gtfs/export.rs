@@ -0,0 +1,299 @@
+use super::{
+    format_date, format_time, route_type, write_records, AgencyRecord, CalendarDateRecord,
+    CalendarRecord, RouteRecord, StopRecord, StopTimeRecord, TripRecord,
+};
+use itertools::Itertools;
+use model::{Date, Day, Schedule, Stop, Timetable};
+use std::collections::HashMap;
+use Res;
+
+/// Feed-level data that the model does not carry.
+#[derive(Debug, Clone)]
+pub struct ExportOptions {
+    pub agency_name: String,
+    pub agency_url: String,
+    pub agency_timezone: String,
+    /// Service period for timetables that are not limited to specific dates.
+    pub start_date: Date,
+    pub end_date: Date,
+}
+
+/// First and last date that timetables are limited to or have exceptions
+/// on, `None` if no timetable refers to dates.
+pub fn service_period(schedules: &[Schedule]) -> Option<(Date, Date)> {
+    schedules
+        .iter()
+        .flat_map(|schedule| &schedule.tracks)
+        .flat_map(|track| &track.timetables)
+        .flat_map(|timetable| {
+            let limits = timetable
+                .valid_dates
+                .iter()
+                .flat_map(|&(first, last)| vec![first, last]);
+            limits
+                .chain(timetable.added_dates.iter().cloned())
+                .chain(timetable.removed_dates.iter().cloned())
+        })
+        .minmax()
+        .into_option()
+}
+
+const AGENCY_ID: &str = "1";
+
+/// Writes stops and schedules as GTFS files, passing each file's name and
+/// contents to `write`. Periodic departures are expanded into separate trips.
+pub fn feed<F>(stops: &[Stop], schedules: &[Schedule], options: &ExportOptions, mut write: F) -> Res
+where
+    F: FnMut(&str, Vec<u8>) -> Res,
+{
+    let agency = AgencyRecord {
+        agency_id: AGENCY_ID.to_owned(),
+        agency_name: options.agency_name.clone(),
+        agency_url: options.agency_url.clone(),
+        agency_timezone: options.agency_timezone.clone(),
+    };
+    write("agency.txt", write_records(&[agency])?)?;
+
+    let stop_records = stops
+        .iter()
+        .map(|stop| StopRecord {
+            stop_id: stop.id.clone(),
+            stop_name: stop.name.clone(),
+            stop_lat: stop.loc.lat,
+            stop_lon: stop.loc.lng,
+            location_type: Some(0),
+        })
+        .collect::<Vec<_>>();
+    write("stops.txt", write_records(&stop_records)?)?;
+
+    let mut services = Services::default();
+    let mut routes = Vec::new();
+    let mut trips = Vec::new();
+    let mut stop_times = Vec::new();
+    for schedule in schedules {
+        routes.push(RouteRecord {
+            route_id: schedule.id.clone(),
+            agency_id: Some(AGENCY_ID.to_owned()),
+            route_short_name: schedule.name.clone(),
+            route_long_name: schedule.long_name.clone(),
+            route_type: route_type(schedule.transport_type),
+        });
+        for (track_index, track) in schedule.tracks.iter().enumerate() {
+            for (tt_index, tt) in track.timetables.iter().enumerate() {
+                let service_id = services.id_of(tt);
+                for (dep_index, departure) in tt.departure_times().enumerate() {
+                    // same indices as `TripId` uses
+                    let trip_id =
+                        format!("{}_{}_{}_{}", schedule.id, track_index, tt_index, dep_index);
+                    for (index, stop_id) in track.stops.iter().enumerate() {
+                        let time = format_time(tt.find_stop_time(index, departure));
+                        stop_times.push(StopTimeRecord {
+                            trip_id: trip_id.clone(),
+                            arrival_time: time.clone(),
+                            departure_time: time,
+                            stop_id: stop_id.clone(),
+                            stop_sequence: index as u32 + 1,
+                        });
+                    }
+                    trips.push(TripRecord {
+                        route_id: schedule.id.clone(),
+                        service_id: service_id.clone(),
+                        trip_id,
+                        trip_headsign: track.name.clone(),
+                        direction_id: None,
+                    });
+                }
+            }
+        }
+    }
+    debug!(
+        "Exporting {} routes, {} trips, {} stop times",
+        routes.len(),
+        trips.len(),
+        stop_times.len()
+    );
+    write("routes.txt", write_records(&routes)?)?;
+    write("trips.txt", write_records(&trips)?)?;
+    write("stop_times.txt", write_records(&stop_times)?)?;
+
+    let (calendar, calendar_dates) = services.records(options);
+    write("calendar.txt", write_records(&calendar)?)?;
+    if !calendar_dates.is_empty() {
+        write("calendar_dates.txt", write_records(&calendar_dates)?)?;
+    }
+    Ok(())
+}
+
+/// Timetables running on the same days share a service id.
+#[derive(Default)]
+struct Services {
+    ids: HashMap<ServiceKey, String>,
+    /// Keys in the order they were assigned ids.
+    keys: Vec<ServiceKey>,
+}
+
+#[derive(PartialEq, Eq, Hash, Clone)]
+struct ServiceKey {
+    days: u8,
+    valid_dates: Option<(Date, Date)>,
+    added_dates: Vec<Date>,
+    removed_dates: Vec<Date>,
+}
+
+impl Services {
+    fn id_of(&mut self, timetable: &Timetable) -> String {
+        let key = ServiceKey {
+            days: timetable.days,
+            valid_dates: timetable.valid_dates,
+            added_dates: timetable.added_dates.clone(),
+            removed_dates: timetable.removed_dates.clone(),
+        };
+        let keys = &mut self.keys;
+        self.ids
+            .entry(key.clone())
+            .or_insert_with(|| {
+                keys.push(key);
+                format!("s{}", keys.len())
+            })
+            .clone()
+    }
+
+    fn records(&self, options: &ExportOptions) -> (Vec<CalendarRecord>, Vec<CalendarDateRecord>) {
+        let mut calendar = Vec::new();
+        let mut calendar_dates = Vec::new();
+        for key in &self.keys {
+            let service_id = &self.ids[key];
+            let runs = |day: Day| (key.days >> day.index()) & 1;
+            let (start, end) = key
+                .valid_dates
+                .unwrap_or((options.start_date, options.end_date));
+            calendar.push(CalendarRecord {
+                service_id: service_id.clone(),
+                monday: runs(Day::Monday),
+                tuesday: runs(Day::Tuesday),
+                wednesday: runs(Day::Wednesday),
+                thursday: runs(Day::Thursday),
+                friday: runs(Day::Friday),
+                saturday: runs(Day::Saturday),
+                sunday: runs(Day::Sunday),
+                start_date: format_date(start),
+                end_date: format_date(end),
+            });
+            let exceptions = key
+                .added_dates
+                .iter()
+                .map(|&date| (date, 1))
+                .chain(key.removed_dates.iter().map(|&date| (date, 2)));
+            for (date, exception_type) in exceptions {
+                calendar_dates.push(CalendarDateRecord {
+                    service_id: service_id.clone(),
+                    date: format_date(date),
+                    exception_type,
+                });
+            }
+        }
+        (calendar, calendar_dates)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::import;
+    use super::*;
+    use model::DayTime;
+
+    const FEED: &[(&str, &str)] = &[
+        (
+            "stops.txt",
+            "stop_id,stop_name,stop_lat,stop_lon\n\
+             a,A,54.69,25.28\n\
+             b,B,54.70,25.28\n",
+        ),
+        (
+            "routes.txt",
+            "route_id,route_short_name,route_long_name,route_type\n\
+             r,1,A - B,3\n",
+        ),
+        (
+            "trips.txt",
+            "route_id,service_id,trip_id\n\
+             r,weekdays,t\n",
+        ),
+        (
+            "stop_times.txt",
+            "trip_id,arrival_time,departure_time,stop_id,stop_sequence\n\
+             t,08:00:00,08:00:00,a,1\n\
+             t,08:07:30,08:07:30,b,2\n",
+        ),
+        (
+            "calendar.txt",
+            "service_id,monday,tuesday,wednesday,thursday,friday,saturday,sunday,start_date,end_date\n\
+             weekdays,1,1,1,1,1,0,0,20180101,20180630\n",
+        ),
+        (
+            "frequencies.txt",
+            "trip_id,start_time,end_time,headway_secs\n\
+             t,08:00:00,09:00:00,1800\n",
+        ),
+    ];
+
+    #[test]
+    fn export_round_trip() {
+        let original = import::feed(|name| {
+            Ok(FEED
+                .iter()
+                .find(|&&(file, _)| file == name)
+                .map(|&(_, contents)| contents.to_owned()))
+        })
+        .unwrap();
+
+        assert_eq!(
+            service_period(&original.schedules),
+            Some((Date::new(2018, 1, 1), Date::new(2018, 6, 30)))
+        );
+
+        let mut files = HashMap::new();
+        feed(
+            &original.stops,
+            &original.schedules,
+            &ExportOptions {
+                agency_name: "Transit".to_owned(),
+                agency_url: "https://example.com".to_owned(),
+                agency_timezone: "Europe/Vilnius".to_owned(),
+                start_date: Date::new(2018, 1, 1),
+                end_date: Date::new(2018, 6, 30),
+            },
+            |name, contents| {
+                files.insert(name.to_owned(), String::from_utf8(contents)?);
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert!(files.contains_key("agency.txt"));
+        assert_eq!(
+            files["stop_times.txt"].lines().nth(4),
+            Some("r_0_0_1,08:37:30,08:37:30,b,2")
+        );
+
+        let exported = import::feed(|name| Ok(files.get(name).cloned())).unwrap();
+        assert_eq!(exported.stops.len(), 2);
+        assert_eq!(exported.schedules.len(), 1);
+        let track = &exported.schedules[0].tracks[0];
+        assert_eq!(track.stops, vec!["a", "b"]);
+        let timetable = &track.timetables[0];
+        assert!(timetable.works_on_day(Day::Monday));
+        assert!(!timetable.works_on_day(Day::Sunday));
+        assert_eq!(
+            timetable.valid_dates,
+            Some((Date::new(2018, 1, 1), Date::new(2018, 6, 30)))
+        );
+        let times = timetable.departure_times().collect::<Vec<_>>();
+        assert_eq!(times, vec![DayTime::new(8, 0), DayTime::new(8, 30)]);
+        assert_eq!(
+            timetable.find_stop_time(1, DayTime::new(8, 30)),
+            DayTime {
+                raw: 8 * 3600 + 37 * 60 + 30
+            }
+        );
+    }
+}
@@ -0,0 +1,526 @@
+use super::{
+    parse_time, read_records, transfers, transport_type, CalendarDateRecord, CalendarRecord, Feed,
+    FrequencyRecord, RouteRecord, StopRecord, StopTimeRecord, TripRecord,
+};
+use model::{
+    Date, Day, DayTime, Departure, Durations, Entry, Periodic, Point, Schedule, Stop, Timetable,
+    Track,
+};
+use std::collections::{HashMap, HashSet};
+use Res;
+
+/// Builds a feed from GTFS files, `read` returns the contents of a file by
+/// its name, or `None` if the feed does not contain it.
+pub fn feed<F>(mut read: F) -> Res<Feed>
+where
+    F: FnMut(&str) -> Res<Option<String>>,
+{
+    let mut required = |name: &str| -> Res<String> {
+        read(name)?.ok_or_else(|| format!("GTFS feed is missing {}", name).into())
+    };
+    let stops = required("stops.txt")?;
+    let routes = required("routes.txt")?;
+    let trips = required("trips.txt")?;
+    let stop_times = required("stop_times.txt")?;
+    let calendar = read("calendar.txt")?;
+    let calendar_dates = read("calendar_dates.txt")?;
+    let frequencies = read("frequencies.txt")?;
+    let transfers = match read("transfers.txt")? {
+        Some(contents) => transfers(&contents)?,
+        None => Vec::new(),
+    };
+
+    let stops = read_records::<StopRecord>(&stops)?
+        .into_iter()
+        .filter(|stop| stop.location_type.unwrap_or(0) == 0)
+        .map(|stop| Stop {
+            id: stop.stop_id,
+            name: stop.stop_name,
+            area: None,
+            direction: None,
+            loc: Point {
+                lat: stop.stop_lat,
+                lng: stop.stop_lon,
+            },
+        })
+        .collect::<Vec<_>>();
+    debug!("Read {} GTFS stops", stops.len());
+
+    let mut services = HashMap::<String, Service>::new();
+    if let Some(calendar) = calendar {
+        for record in read_records::<CalendarRecord>(&calendar)? {
+            let service = services.entry(record.service_id.clone()).or_default();
+            service.days = service_days(&record);
+            service.valid_dates = Some((
+                record.start_date.parse::<Date>()?,
+                record.end_date.parse::<Date>()?,
+            ));
+        }
+    }
+    if let Some(calendar_dates) = calendar_dates {
+        for record in read_records::<CalendarDateRecord>(&calendar_dates)? {
+            let service = services.entry(record.service_id).or_default();
+            let date = record.date.parse::<Date>()?;
+            match record.exception_type {
+                1 => service.added_dates.push(date),
+                2 => service.removed_dates.push(date),
+                other => return Err(format!("invalid exception type: {}", other).into()),
+            }
+        }
+    }
+
+    let dated_only = dated_only_services(&mut services);
+    if dated_only > 0 {
+        warn!(
+            "{} services are only listed in calendar_dates.txt, their days of week \
+             are taken from the first week of the feed",
+            dated_only
+        );
+    }
+
+    let mut frequencies_by_trip = HashMap::<String, Vec<Periodic>>::new();
+    if let Some(frequencies) = frequencies {
+        for record in read_records::<FrequencyRecord>(&frequencies)? {
+            let period = Periodic {
+                from: parse_time(&record.start_time)?,
+                to: parse_time(&record.end_time)?,
+                headway: record.headway_secs,
+            };
+            // the trip is still known to run by frequencies, just not then
+            let periods = frequencies_by_trip
+                .entry(record.trip_id.clone())
+                .or_default();
+            if period.is_empty() {
+                warn!(
+                    "Skipping frequency {}-{} with headway {} of trip {}",
+                    record.start_time, record.end_time, record.headway_secs, record.trip_id
+                );
+                continue;
+            }
+            periods.push(period);
+        }
+    }
+
+    let mut stop_times_by_trip = HashMap::<String, Vec<StopTimeRecord>>::new();
+    for record in read_records::<StopTimeRecord>(&stop_times)? {
+        stop_times_by_trip
+            .entry(record.trip_id.clone())
+            .or_default()
+            .push(record);
+    }
+
+    let mut builders = Vec::new();
+    let mut builder_by_route = HashMap::new();
+    for route in read_records::<RouteRecord>(&routes)? {
+        builder_by_route.insert(route.route_id.clone(), builders.len());
+        let name = if route.route_short_name.is_empty() {
+            route.route_long_name.clone()
+        } else {
+            route.route_short_name
+        };
+        builders.push(ScheduleBuilder {
+            schedule: Schedule {
+                id: route.route_id,
+                name,
+                long_name: route.route_long_name,
+                tracks: Vec::new(),
+                transport_type: transport_type(route.route_type),
+            },
+            tracks: HashMap::new(),
+            timetables: HashMap::new(),
+        });
+    }
+
+    let known_stops = stops.iter().map(|s| s.id.as_str()).collect::<HashSet<_>>();
+    for trip in read_records::<TripRecord>(&trips)? {
+        let builder = match builder_by_route.get(&trip.route_id) {
+            Some(&index) => &mut builders[index],
+            None => {
+                warn!("Trip {} refers to unknown route", trip.trip_id);
+                continue;
+            }
+        };
+        let mut stop_times = match stop_times_by_trip.remove(&trip.trip_id) {
+            Some(stop_times) => stop_times,
+            None => continue,
+        };
+        stop_times.sort_by_key(|st| st.stop_sequence);
+        if stop_times.len() < 2 {
+            continue;
+        }
+        if let Some(st) = stop_times
+            .iter()
+            .find(|st| !known_stops.contains(st.stop_id.as_str()))
+        {
+            warn!(
+                "Skipping trip {}, it refers to unknown stop {}",
+                trip.trip_id, st.stop_id
+            );
+            continue;
+        }
+        let times = match stop_times_of(&stop_times)? {
+            Some(times) => times,
+            None => {
+                warn!(
+                    "Skipping trip {}, it has no times at its first or last stop",
+                    trip.trip_id
+                );
+                continue;
+            }
+        };
+        let stop_ids = stop_times.into_iter().map(|st| st.stop_id).collect();
+        let service = services.get(&trip.service_id).cloned().unwrap_or_default();
+        let periods = frequencies_by_trip.remove(&trip.trip_id);
+        builder.add_trip(&trip, stop_ids, &times, service, periods);
+    }
+
+    let schedules = builders
+        .into_iter()
+        .map(|builder| builder.schedule)
+        .filter(|schedule| !schedule.tracks.is_empty())
+        .collect::<Vec<_>>();
+    debug!("Read {} GTFS routes", schedules.len());
+
+    Ok(Feed {
+        stops,
+        schedules,
+        transfers,
+    })
+}
+
+#[derive(Default, Clone)]
+struct Service {
+    days: u8,
+    valid_dates: Option<(Date, Date)>,
+    added_dates: Vec<Date>,
+    removed_dates: Vec<Date>,
+}
+
+/// Gives services without a calendar.txt entry the days of week of their
+/// dates in the first week of the feed, and limits their days to that week so
+/// that later dates rely on their added dates only. Returns their count.
+fn dated_only_services(services: &mut HashMap<String, Service>) -> usize {
+    let first_date = services
+        .values()
+        .flat_map(|service| {
+            let start = service.valid_dates.map(|(start, _)| start);
+            start.into_iter().chain(service.added_dates.iter().cloned())
+        })
+        .min();
+    let week = match first_date {
+        Some(date) => (date, date.offset(6)),
+        None => return 0,
+    };
+    let mut count = 0;
+    for service in services.values_mut().filter(|s| s.valid_dates.is_none()) {
+        service.days = service
+            .added_dates
+            .iter()
+            .filter(|&&date| week.0 <= date && date <= week.1)
+            .fold(0, |days, date| days | 1 << date.weekday().index());
+        service.valid_dates = Some(week);
+        count += 1;
+    }
+    count
+}
+
+fn service_days(record: &CalendarRecord) -> u8 {
+    let flags = [
+        (Day::Monday, record.monday),
+        (Day::Tuesday, record.tuesday),
+        (Day::Wednesday, record.wednesday),
+        (Day::Thursday, record.thursday),
+        (Day::Friday, record.friday),
+        (Day::Saturday, record.saturday),
+        (Day::Sunday, record.sunday),
+    ];
+    flags
+        .iter()
+        .filter(|&&(_, flag)| flag == 1)
+        .fold(0, |days, &(day, _)| days | 1 << day.index())
+}
+
+/// Times at each stop of a trip. Stops without times get them interpolated
+/// linearly between the neighbouring timed stops. `None` if the first or the
+/// last stop has no time.
+fn stop_times_of(stop_times: &[StopTimeRecord]) -> Res<Option<Vec<DayTime>>> {
+    let mut known = Vec::new();
+    for (index, st) in stop_times.iter().enumerate() {
+        let time = if !st.departure_time.is_empty() {
+            &st.departure_time
+        } else if !st.arrival_time.is_empty() {
+            &st.arrival_time
+        } else {
+            continue;
+        };
+        known.push((index, parse_time(time)?.raw));
+    }
+    let first_known = known.first().map(|&(index, _)| index);
+    let last_known = known.last().map(|&(index, _)| index);
+    if first_known != Some(0) || last_known != Some(stop_times.len() - 1) {
+        return Ok(None);
+    }
+
+    let mut times = Vec::with_capacity(stop_times.len());
+    for (&(a, a_time), &(b, b_time)) in known.iter().zip(known.iter().skip(1)) {
+        for index in a..b {
+            let raw = a_time + b_time.saturating_sub(a_time) * (index - a) as u64 / (b - a) as u64;
+            times.push(DayTime { raw });
+        }
+    }
+    times.push(DayTime {
+        raw: known[known.len() - 1].1,
+    });
+    Ok(Some(times))
+}
+
+struct ScheduleBuilder {
+    schedule: Schedule,
+    /// Track index by direction and sequence of stops.
+    tracks: HashMap<(Option<u32>, Vec<String>), usize>,
+    /// Timetable index and its first departure times, by track index and
+    /// service id.
+    timetables: HashMap<(usize, String), (usize, HashSet<DayTime>)>,
+}
+
+impl ScheduleBuilder {
+    fn add_trip(
+        &mut self,
+        trip: &TripRecord,
+        stops: Vec<String>,
+        times: &[DayTime],
+        service: Service,
+        periods: Option<Vec<Periodic>>,
+    ) {
+        let key = (trip.direction_id, stops);
+        let track_index = match self.tracks.get(&key) {
+            Some(&index) => index,
+            None => {
+                let index = self.schedule.tracks.len();
+                let name = if trip.trip_headsign.is_empty() {
+                    key.1[key.1.len() - 1].clone()
+                } else {
+                    trip.trip_headsign.clone()
+                };
+                self.schedule.tracks.push(Track {
+                    name,
+                    stops: key.1.clone(),
+                    timetables: Vec::new(),
+                });
+                self.tracks.insert(key, index);
+                index
+            }
+        };
+        let track = &mut self.schedule.tracks[track_index];
+
+        let timetable_key = (track_index, trip.service_id.clone());
+        let &mut (timetable_index, ref mut departures) =
+            self.timetables.entry(timetable_key).or_insert_with(|| {
+                track.timetables.push(Timetable {
+                    days: service.days,
+                    departures: Vec::new(),
+                    durations: vec![
+                        Durations {
+                            entries: Vec::new()
+                        };
+                        times.len()
+                    ],
+                    added_dates: service.added_dates,
+                    removed_dates: service.removed_dates,
+                    valid_dates: service.valid_dates,
+                });
+                (track.timetables.len() - 1, HashSet::new())
+            });
+        let timetable = &mut track.timetables[timetable_index];
+
+        let first = times[0];
+        let periods = periods.unwrap_or_else(|| {
+            vec![Periodic {
+                from: first,
+                to: first.offset(1),
+                headway: 0,
+            }]
+        });
+        for period in periods {
+            let departure = if period.headway == 0 {
+                Departure::Exact(period.from)
+            } else {
+                Departure::Periodic(period)
+            };
+            if !departures.insert(period.from) {
+                debug!("Skipping trip {} with a duplicate departure", trip.trip_id);
+                continue;
+            }
+            timetable.departures.push(departure);
+            for (durations, time) in timetable.durations.iter_mut().zip(times) {
+                durations.entries.push(Entry {
+                    from: period.from,
+                    to: period.to,
+                    time: time.raw - first.raw,
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use model::Calendar;
+
+    const STOPS: &str = "\
+stop_id,stop_name,stop_lat,stop_lon,location_type
+s1,Žaliasis tiltas,54.69236,25.28048,0
+s2,Lukiškių aikštė,54.68940,25.27100,0
+s3,Seimas,54.68830,25.26250,
+st,Station,54.68830,25.26250,1
+";
+    const ROUTES: &str = "\
+route_id,route_short_name,route_long_name,route_type
+r3,3G,Stotis - Fabijoniškės,800
+";
+    const TRIPS: &str = "\
+route_id,service_id,trip_id,trip_headsign,direction_id
+r3,weekdays,t1,Fabijoniškės,0
+r3,weekdays,t2,Fabijoniškės,0
+r3,weekends,t3,Fabijoniškės,0
+";
+    const STOP_TIMES: &str = "\
+trip_id,arrival_time,departure_time,stop_id,stop_sequence
+t1,07:00:00,07:00:00,s1,1
+t1,,,s2,2
+t1,07:10:00,07:10:00,s3,3
+t2,24:10:00,24:10:00,s1,1
+t2,24:12:00,24:12:00,s2,2
+t2,24:15:00,24:15:00,s3,3
+t3,06:00:00,06:00:00,s1,1
+t3,06:04:00,06:04:00,s2,2
+t3,06:08:00,06:08:00,s3,3
+";
+    const CALENDAR: &str = "\
+service_id,monday,tuesday,wednesday,thursday,friday,saturday,sunday,start_date,end_date
+weekdays,1,1,1,1,1,0,0,20180101,20181231
+weekends,0,0,0,0,0,1,1,20180101,20181231
+";
+    const CALENDAR_DATES: &str = "\
+service_id,date,exception_type
+weekdays,20180706,2
+weekends,20180706,1
+";
+    const FREQUENCIES: &str = "\
+trip_id,start_time,end_time,headway_secs
+t3,06:00:00,08:00:00,1200
+";
+    const TRANSFERS: &str = "\
+from_stop_id,to_stop_id,transfer_type,min_transfer_time
+s1,s2,2,180
+s2,s3,3,
+s3,s3,2,60
+s1,s3,0,
+";
+
+    fn test_feed() -> Feed {
+        let mut files = HashMap::new();
+        files.insert("stops.txt", STOPS);
+        files.insert("routes.txt", ROUTES);
+        files.insert("trips.txt", TRIPS);
+        files.insert("stop_times.txt", STOP_TIMES);
+        files.insert("calendar.txt", CALENDAR);
+        files.insert("calendar_dates.txt", CALENDAR_DATES);
+        files.insert("frequencies.txt", FREQUENCIES);
+        files.insert("transfers.txt", TRANSFERS);
+        feed(|name| Ok(files.get(name).map(|s| s.to_string()))).unwrap()
+    }
+
+    #[test]
+    fn import_feed() {
+        let feed = test_feed();
+        assert_eq!(feed.stops.len(), 3);
+        assert_eq!(feed.schedules.len(), 1);
+
+        let schedule = &feed.schedules[0];
+        assert_eq!(schedule.name, "3G");
+        assert_eq!(schedule.tracks.len(), 1);
+
+        let track = &schedule.tracks[0];
+        assert_eq!(track.stops, vec!["s1", "s2", "s3"]);
+        assert_eq!(track.timetables.len(), 2);
+
+        let weekdays = &track.timetables[0];
+        assert!(weekdays.works_on_day(Day::Friday));
+        assert!(!weekdays.works_on_day(Day::Saturday));
+        assert_eq!(weekdays.removed_dates, vec![Date::new(2018, 7, 6)]);
+        let times = weekdays.departure_times().collect::<Vec<_>>();
+        assert_eq!(times, vec![DayTime::new(7, 0), DayTime { raw: 87000 }]);
+        // untimed stop is interpolated
+        assert_eq!(
+            weekdays.find_stop_time(1, DayTime::new(7, 0)),
+            DayTime::new(7, 5)
+        );
+        assert_eq!(
+            weekdays.find_stop_time(2, DayTime { raw: 87000 }),
+            DayTime { raw: 87300 }
+        );
+
+        let weekends = &track.timetables[1];
+        assert_eq!(weekends.departure_times().count(), 6);
+        assert_eq!(
+            weekends.find_stop_time(2, DayTime::new(7, 40)),
+            DayTime::new(7, 48)
+        );
+
+        let transfers = feed
+            .transfers
+            .iter()
+            .map(|t| (t.from.as_str(), t.to.as_str(), t.distance.map(f64::round)))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            transfers,
+            vec![("s1", "s2", Some(200.0)), ("s2", "s3", None)]
+        );
+    }
+
+    #[test]
+    fn services_without_calendar() {
+        let trips = "\
+route_id,service_id,trip_id,trip_headsign,direction_id
+r3,special,t1,Fabijoniškės,0
+";
+        let calendar_dates = "\
+service_id,date,exception_type
+special,20180104,1
+special,20180709,1
+";
+        let mut files = HashMap::new();
+        files.insert("stops.txt", STOPS);
+        files.insert("routes.txt", ROUTES);
+        files.insert("trips.txt", trips);
+        files.insert("stop_times.txt", STOP_TIMES);
+        files.insert("calendar_dates.txt", calendar_dates);
+        let feed = feed(|name| Ok(files.get(name).map(|s| s.to_string()))).unwrap();
+
+        let timetable = &feed.schedules[0].tracks[0].timetables[0];
+        // 2018-01-04 is a Thursday
+        assert!(timetable.works_on_day(Day::Thursday));
+        assert!(!timetable.works_on_day(Day::Monday));
+        let calendar = Calendar::default();
+        assert!(timetable.works_on_date(Date::new(2018, 7, 9), &calendar));
+        assert!(!timetable.works_on_date(Date::new(2018, 7, 12), &calendar));
+    }
+
+    #[test]
+    fn skips_trips_without_end_times() {
+        let stop_times = STOP_TIMES.replace("t2,24:15:00,24:15:00,s3,3", "t2,,,s3,3");
+        let mut files = HashMap::new();
+        files.insert("stops.txt", STOPS);
+        files.insert("routes.txt", ROUTES);
+        files.insert("trips.txt", TRIPS);
+        files.insert("stop_times.txt", &stop_times);
+        files.insert("calendar.txt", CALENDAR);
+        let feed = feed(|name| Ok(files.get(name).map(|s| s.to_string()))).unwrap();
+
+        let weekdays = &feed.schedules[0].tracks[0].timetables[0];
+        let times = weekdays.departure_times().collect::<Vec<_>>();
+        assert_eq!(times, vec![DayTime::new(7, 0)]);
+    }
+}
@@ -0,0 +1,271 @@
+use csv;
+use model::{Date, DayTime, Schedule, Stop, Transfer, TransportType};
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::Path;
+use zip::write::{FileOptions, ZipWriter};
+use zip::ZipArchive;
+use Res;
+
+mod export;
+mod import;
+
+pub use self::export::{service_period, ExportOptions};
+
+// Walking speed assumed when converting transfer times to distances, in
+// meters per second.
+const WALK_SPEED: f64 = 4.0 * 1000.0 / 3600.0;
+
+/// Stops, schedules and transfers read from a GTFS feed.
+#[derive(Debug, Clone)]
+pub struct Feed {
+    pub stops: Vec<Stop>,
+    pub schedules: Vec<Schedule>,
+    pub transfers: Vec<Transfer>,
+}
+
+/// Loads a GTFS feed from either a zip archive or a directory containing
+/// the extracted `.txt` files.
+pub fn load<P: AsRef<Path>>(path: P) -> Res<Feed> {
+    let path = path.as_ref();
+    if path.is_dir() {
+        import::feed(|name| read_dir_file(path, name))
+    } else {
+        let mut archive = ZipArchive::new(File::open(path)?)?;
+        import::feed(|name| read_zip_file(&mut archive, name))
+    }
+}
+
+/// Writes stops and schedules as a GTFS feed. If the path ends with `.zip`
+/// the feed is written as a zip archive, otherwise as a directory of `.txt`
+/// files.
+pub fn save<P: AsRef<Path>>(
+    path: P,
+    stops: &[Stop],
+    schedules: &[Schedule],
+    options: &ExportOptions,
+) -> Res {
+    let path = path.as_ref();
+    if path.extension() == Some("zip".as_ref()) {
+        let mut zip = ZipWriter::new(File::create(path)?);
+        export::feed(stops, schedules, options, |name, contents| {
+            zip.start_file(name, FileOptions::default())?;
+            zip.write_all(&contents)?;
+            Ok(())
+        })?;
+        zip.finish()?;
+        Ok(())
+    } else {
+        fs::create_dir_all(path)?;
+        export::feed(stops, schedules, options, |name, contents| {
+            fs::write(path.join(name), contents)?;
+            Ok(())
+        })
+    }
+}
+
+/// Reads walking transfers from the contents of a GTFS `transfers.txt`.
+/// Minimum transfer times become walking distances at 4 km/h, and
+/// transfers marked as impossible forbid walking. Other transfers do not
+/// say anything about walking and are skipped.
+pub fn transfers(contents: &str) -> Res<Vec<Transfer>> {
+    let transfers = read_records::<TransferRecord>(contents)?
+        .into_iter()
+        .filter(|record| record.from_stop_id != record.to_stop_id)
+        .filter_map(|record| {
+            let distance = match (record.transfer_type, record.min_transfer_time) {
+                (Some(3), _) => None,
+                (_, Some(time)) => Some(time as f64 * WALK_SPEED),
+                _ => return None,
+            };
+            Some(Transfer {
+                from: record.from_stop_id,
+                to: record.to_stop_id,
+                distance,
+            })
+        })
+        .collect::<Vec<_>>();
+    debug!("Read {} GTFS transfers", transfers.len());
+    Ok(transfers)
+}
+
+fn read_dir_file(dir: &Path, name: &str) -> Res<Option<String>> {
+    let path = dir.join(name);
+    if !path.exists() {
+        return Ok(None);
+    }
+    Ok(Some(fs::read_to_string(path)?))
+}
+
+fn read_zip_file(archive: &mut ZipArchive<File>, name: &str) -> Res<Option<String>> {
+    let mut file = match archive.by_name(name) {
+        Ok(file) => file,
+        Err(::zip::result::ZipError::FileNotFound) => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)?;
+    Ok(Some(contents))
+}
+
+fn read_records<T>(contents: &str) -> Res<Vec<T>>
+where
+    for<'de> T: ::serde::Deserialize<'de>,
+{
+    // feeds exported on Windows often start with a byte order mark
+    let contents = contents.trim_start_matches('\u{feff}');
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(contents.as_bytes());
+    let mut records = Vec::new();
+    for record in reader.deserialize() {
+        records.push(record?);
+    }
+    Ok(records)
+}
+
+fn write_records<T: ::serde::Serialize>(records: &[T]) -> Res<Vec<u8>> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    for record in records {
+        writer.serialize(record)?;
+    }
+    Ok(writer.into_inner().map_err(|e| e.to_string())?)
+}
+
+/// Parses GTFS time of day, `H:MM:SS`. Hours may exceed 23 for trips
+/// continuing past midnight.
+fn parse_time(s: &str) -> Res<DayTime> {
+    let parts = s
+        .split(':')
+        .map(|part| part.parse::<u64>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| format!("invalid time: {}", s))?;
+    match parts[..] {
+        [hours, minutes, seconds] if minutes < 60 && seconds < 60 => Ok(DayTime {
+            raw: hours * 3600 + minutes * 60 + seconds,
+        }),
+        _ => Err(format!("invalid time: {}", s).into()),
+    }
+}
+
+fn format_time(time: DayTime) -> String {
+    let raw = time.raw;
+    format!("{:02}:{:02}:{:02}", raw / 3600, raw / 60 % 60, raw % 60)
+}
+
+fn format_date(date: Date) -> String {
+    format!("{:04}{:02}{:02}", date.year, date.month, date.day)
+}
+
+fn route_type(transport_type: TransportType) -> u32 {
+    match transport_type {
+        TransportType::Trolley => 800,
+        TransportType::Bus => 3,
+        TransportType::Express => 702,
+        TransportType::NightBus => 705,
+    }
+}
+
+fn transport_type(route_type: u32) -> TransportType {
+    match route_type {
+        11 | 800..=899 => TransportType::Trolley,
+        702 => TransportType::Express,
+        705 => TransportType::NightBus,
+        3 | 700..=799 => TransportType::Bus,
+        other => {
+            debug!("Treating unsupported route type {} as bus", other);
+            TransportType::Bus
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct AgencyRecord {
+    agency_id: String,
+    agency_name: String,
+    agency_url: String,
+    agency_timezone: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StopRecord {
+    stop_id: String,
+    stop_name: String,
+    stop_lat: f64,
+    stop_lon: f64,
+    #[serde(default)]
+    location_type: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RouteRecord {
+    route_id: String,
+    #[serde(default)]
+    agency_id: Option<String>,
+    #[serde(default)]
+    route_short_name: String,
+    #[serde(default)]
+    route_long_name: String,
+    route_type: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct TripRecord {
+    route_id: String,
+    service_id: String,
+    trip_id: String,
+    #[serde(default)]
+    trip_headsign: String,
+    #[serde(default)]
+    direction_id: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StopTimeRecord {
+    trip_id: String,
+    #[serde(default)]
+    arrival_time: String,
+    #[serde(default)]
+    departure_time: String,
+    stop_id: String,
+    stop_sequence: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CalendarRecord {
+    service_id: String,
+    monday: u8,
+    tuesday: u8,
+    wednesday: u8,
+    thursday: u8,
+    friday: u8,
+    saturday: u8,
+    sunday: u8,
+    start_date: String,
+    end_date: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CalendarDateRecord {
+    service_id: String,
+    date: String,
+    exception_type: u8,
+}
+
+#[derive(Serialize, Deserialize)]
+struct FrequencyRecord {
+    trip_id: String,
+    start_time: String,
+    end_time: String,
+    headway_secs: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct TransferRecord {
+    from_stop_id: String,
+    to_stop_id: String,
+    #[serde(default)]
+    transfer_type: Option<u32>,
+    #[serde(default)]
+    min_transfer_time: Option<u64>,
+}
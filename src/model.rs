@@ -1,12 +1,15 @@
 use serde;
+use serde::ser::{Serialize, SerializeStruct, Serializer};
 use std::cmp::Ordering;
+use std::collections::HashMap;
 use std::fmt;
+use std::str::FromStr;
 
-#[derive(Deserialize, Debug, Copy, Clone)]
+#[derive(Serialize, Deserialize, Debug, Copy, Clone)]
 pub struct Point {
-    #[serde(rename = "Lat")]
+    #[serde(rename(deserialize = "Lat"))]
     pub lat: f64,
-    #[serde(rename = "Lng")]
+    #[serde(rename(deserialize = "Lng"))]
     pub lng: f64,
 }
 
@@ -29,16 +32,61 @@ impl Point {
     }
 }
 
+impl FromStr for Point {
+    type Err = String;
+
+    /// Parses coordinates formatted as `lat,lng`.
+    fn from_str(s: &str) -> Result<Point, String> {
+        let invalid = || format!("invalid coordinates: {}", s);
+        let mut parts = s.splitn(2, ',');
+        let lat = parts
+            .next()
+            .and_then(|lat| lat.trim().parse().ok())
+            .ok_or_else(invalid)?;
+        let lng = parts
+            .next()
+            .and_then(|lng| lng.trim().parse().ok())
+            .ok_or_else(invalid)?;
+        Ok(Point { lat, lng })
+    }
+}
+
 #[derive(Deserialize, Debug, Clone)]
 pub struct Stop {
     #[serde(rename = "Id")]
     pub id: String,
     #[serde(rename = "Name")]
     pub name: String,
+    /// Street or square the stop is at.
+    #[serde(rename = "AreaName", default)]
+    pub area: Option<String>,
+    /// Where vehicles leaving the stop are heading.
+    #[serde(rename = "Direction", default)]
+    pub direction: Option<String>,
     #[serde(flatten)]
     pub loc: Point,
 }
 
+/// Streets and paths one can walk along, as points joined by straight
+/// lines.
+#[derive(Debug, Clone, Default)]
+pub struct Streets {
+    pub nodes: Vec<Point>,
+    /// Pairs of indices in `nodes` one can walk between in both directions.
+    pub edges: Vec<(usize, usize)>,
+}
+
+/// A walking connection between two stops that overrides the one derived
+/// from their distance.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transfer {
+    pub from: String,
+    pub to: String,
+    /// Walking distance in meters, `None` if one cannot walk between the
+    /// stops at all.
+    pub distance: Option<f64>,
+}
+
 #[derive(Deserialize, Debug, Clone)]
 pub struct Schedule {
     #[serde(rename = "Id")]
@@ -53,18 +101,33 @@ pub struct Schedule {
     pub transport_type: TransportType,
 }
 
-#[derive(Deserialize, PartialEq, Eq, Debug, Copy, Clone)]
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Copy, Clone)]
 pub enum TransportType {
-    #[serde(rename = "vln_trol")]
+    #[serde(rename(deserialize = "vln_trol", serialize = "trolley"))]
     Trolley,
-    #[serde(rename = "vln_bus")]
+    #[serde(rename(deserialize = "vln_bus", serialize = "bus"))]
     Bus,
-    #[serde(rename = "vln_expressbus")]
+    #[serde(rename(deserialize = "vln_expressbus", serialize = "express"))]
     Express,
-    #[serde(rename = "vln_nightbus")]
+    #[serde(rename(deserialize = "vln_nightbus", serialize = "night_bus"))]
     NightBus,
 }
 
+impl FromStr for TransportType {
+    type Err = String;
+
+    /// Parses the names used when serializing, e.g. `night_bus`.
+    fn from_str(s: &str) -> Result<TransportType, String> {
+        match s.to_lowercase().as_str() {
+            "trolley" => Ok(TransportType::Trolley),
+            "bus" => Ok(TransportType::Bus),
+            "express" => Ok(TransportType::Express),
+            "night_bus" => Ok(TransportType::NightBus),
+            _ => Err(format!("invalid transport type: {}", s)),
+        }
+    }
+}
+
 impl fmt::Display for TransportType {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
@@ -107,6 +170,26 @@ pub struct Timetable {
     pub departures: Vec<Departure>,
     #[serde(rename = "StopDurations")]
     pub durations: Vec<Durations>,
+    /// Dates on which this timetable runs regardless of `days`.
+    #[serde(rename = "AddedDates", default, deserialize_with = "de_dates")]
+    pub added_dates: Vec<Date>,
+    /// Dates on which this timetable does not run regardless of `days`.
+    #[serde(rename = "RemovedDates", default, deserialize_with = "de_dates")]
+    pub removed_dates: Vec<Date>,
+    /// First and last date on which this timetable runs, if limited.
+    #[serde(skip)]
+    pub valid_dates: Option<(Date, Date)>,
+}
+
+fn de_dates<'de, D>(deserializer: D) -> Result<Vec<Date>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let dates: Vec<String> = serde::Deserialize::deserialize(deserializer)?;
+    dates
+        .iter()
+        .map(|date| date.parse().map_err(serde::de::Error::custom))
+        .collect()
 }
 
 impl Timetable {
@@ -125,9 +208,27 @@ impl Timetable {
 }
 
 impl Timetable {
+    /// All departure times from the first stop, with periodic departures
+    /// expanded into individual trips.
+    pub fn departure_times<'a>(&'a self) -> impl Iterator<Item = DayTime> + 'a {
+        self.departures.iter().flat_map(|dep| dep.times())
+    }
+
     pub fn works_on_day(&self, day: Day) -> bool {
         let flag = 1 << day.index();
-        (self.days | flag) != 0
+        (self.days & flag) != 0
+    }
+
+    pub fn works_on_date(&self, date: Date, calendar: &Calendar) -> bool {
+        if self.removed_dates.contains(&date) {
+            false
+        } else if self.added_dates.contains(&date) {
+            true
+        } else if let Some((first, last)) = self.valid_dates {
+            first <= date && date <= last && self.works_on_day(calendar.service_day(date))
+        } else {
+            self.works_on_day(calendar.service_day(date))
+        }
     }
 }
 
@@ -138,7 +239,18 @@ pub enum Departure {
     Periodic(Periodic),
 }
 
-#[derive(Deserialize, PartialEq, Eq, PartialOrd, Ord, Debug, Copy, Clone)]
+impl Departure {
+    pub fn times(&self) -> Vec<DayTime> {
+        match *self {
+            Departure::Exact(time) => vec![time],
+            Departure::Periodic(ref periodic) => periodic.times().collect(),
+        }
+    }
+}
+
+/// Seconds since midnight. Trips running past midnight have times of 24:00
+/// and later, which `Timestamp` moves to the next day.
+#[derive(Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Copy, Clone)]
 pub struct DayTime {
     #[serde(rename = "Time")]
     pub raw: u64,
@@ -166,6 +278,28 @@ impl DayTime {
     }
 }
 
+impl FromStr for DayTime {
+    type Err = String;
+
+    /// Parses time of day formatted as `HH:MM`.
+    fn from_str(s: &str) -> Result<DayTime, String> {
+        let invalid = || format!("invalid time: {}", s);
+        let mut parts = s.splitn(2, ':');
+        let hours = parts
+            .next()
+            .and_then(|h| h.parse().ok())
+            .ok_or_else(invalid)?;
+        let minutes = parts
+            .next()
+            .and_then(|m| m.parse().ok())
+            .ok_or_else(invalid)?;
+        if hours >= 24 || minutes >= 60 {
+            return Err(invalid());
+        }
+        Ok(DayTime::new(hours, minutes))
+    }
+}
+
 impl fmt::Display for DayTime {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let minutes = self.raw / 60 % 60;
@@ -184,9 +318,33 @@ impl fmt::Display for DayTime {
 #[derive(Deserialize, Debug, Copy, Clone)]
 pub struct Periodic {
     #[serde(rename = "FromTime", deserialize_with = "de_day_time")]
-    from: DayTime,
+    pub from: DayTime,
     #[serde(rename = "ToTime", deserialize_with = "de_day_time")]
-    to: DayTime,
+    pub to: DayTime,
+    /// Time between consecutive departures, in seconds. Periods without a
+    /// headway have no departures and are skipped when loading.
+    #[serde(rename = "Headway", default)]
+    pub headway: u64,
+}
+
+impl Periodic {
+    /// Departure times of individual trips, starting at `from` and repeating
+    /// every `headway` seconds while before `to`.
+    pub fn times(&self) -> impl Iterator<Item = DayTime> {
+        let Periodic { from, to, headway } = *self;
+        let count = if self.is_empty() {
+            0
+        } else {
+            (to.raw - from.raw).div_ceil(headway)
+        };
+        (0..count).map(move |i| from.offset(i * headway))
+    }
+
+    /// Returns if the period has no departures, lacking a headway or ending
+    /// before it starts.
+    pub fn is_empty(&self) -> bool {
+        self.headway == 0 || self.to <= self.from
+    }
 }
 
 fn de_day_time<'de, D>(deserializer: D) -> Result<DayTime, D::Error>
@@ -213,7 +371,7 @@ pub struct Entry {
     pub time: u64,
 }
 
-#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Copy, Clone)]
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Copy, Clone)]
 pub enum Day {
     Monday,
     Tuesday,
@@ -253,6 +411,27 @@ impl fmt::Display for Day {
     }
 }
 
+impl Serialize for Day {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl FromStr for Day {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Day, String> {
+        let lower = s.to_lowercase();
+        DAYS.iter()
+            .cloned()
+            .find(|day| {
+                let short = day.to_string().to_lowercase();
+                lower.starts_with(&short) && format!("{:?}", day).to_lowercase().starts_with(&lower)
+            })
+            .ok_or_else(|| format!("invalid day: {}", s))
+    }
+}
+
 pub const DAYS: &[Day] = &[
     Day::Monday,
     Day::Tuesday,
@@ -263,79 +442,272 @@ pub const DAYS: &[Day] = &[
     Day::Sunday,
 ];
 
+/// A calendar date in the proleptic Gregorian calendar.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Copy, Clone)]
+pub struct Date {
+    pub year: i32,
+    pub month: u32,
+    pub day: u32,
+}
+
+impl Date {
+    pub fn new(year: i32, month: u32, day: u32) -> Date {
+        assert!(
+            (1..=12).contains(&month),
+            "month should be in range [1; 12]"
+        );
+        assert!(
+            (1..=days_in_month(year, month)).contains(&day),
+            "day should be in range of the month"
+        );
+        Date { year, month, day }
+    }
+
+    pub fn weekday(&self) -> Day {
+        // 1970-01-01 was a Thursday
+        let index = (self.days_since_epoch() + 3).rem_euclid(7);
+        DAYS[index as usize]
+    }
+
+    pub fn offset(&self, days: i64) -> Date {
+        Date::from_days_since_epoch(self.days_since_epoch() + days)
+    }
+
+    // Algorithms from http://howardhinnant.github.io/date_algorithms.html
+    fn days_since_epoch(&self) -> i64 {
+        let y = i64::from(self.year) - if self.month <= 2 { 1 } else { 0 };
+        let m = i64::from(self.month);
+        let d = i64::from(self.day);
+        let era = y.div_euclid(400);
+        let yoe = y - era * 400;
+        let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + d - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        era * 146_097 + doe - 719_468
+    }
+
+    fn from_days_since_epoch(days: i64) -> Date {
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z - era * 146_097;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+        Date {
+            year: year as i32,
+            month: month as u32,
+            day: day as u32,
+        }
+    }
+}
+
+fn days_in_month(year: i32, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+impl FromStr for Date {
+    type Err = String;
+
+    /// Parses dates formatted either as `YYYY-MM-DD` or as `YYYYMMDD`.
+    fn from_str(s: &str) -> Result<Date, String> {
+        let digits = s.replace('-', "");
+        let invalid = || format!("invalid date: {}", s);
+        if digits.len() != 8 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(invalid());
+        }
+        let year = digits[0..4].parse().map_err(|_| invalid())?;
+        let month = digits[4..6].parse().map_err(|_| invalid())?;
+        let day = digits[6..8].parse().map_err(|_| invalid())?;
+        if !(1..=12).contains(&month) || !(1..=days_in_month(year, month)).contains(&day) {
+            return Err(invalid());
+        }
+        Ok(Date { year, month, day })
+    }
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+/// Service calendar, mapping concrete dates to the day of week whose
+/// timetables run on that date.
+#[derive(Debug, Default, Clone)]
+pub struct Calendar {
+    /// Dates that run the service of a different day, e.g. public holidays
+    /// that run Sunday service.
+    pub overrides: HashMap<Date, Day>,
+}
+
+impl Calendar {
+    pub fn service_day(&self, date: Date) -> Day {
+        self.overrides
+            .get(&date)
+            .cloned()
+            .unwrap_or_else(|| date.weekday())
+    }
+}
+
+/// A moment in the week. Times past midnight are normalized to the next
+/// day, and the week wraps from Sunday to Monday.
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone)]
 pub struct Timestamp {
-    pub day: Day,
-    pub time: DayTime,
+    /// Seconds since the start of the week, Monday midnight.
+    seconds: u64,
 }
 
 impl Timestamp {
+    /// Timestamp for `time` on `day`, which may be past midnight.
     pub fn new(day: Day, time: DayTime) -> Timestamp {
-        Timestamp { day, time }
+        let day = DAYS.iter().position(|&d| d == day).unwrap() as u64;
+        Timestamp::from_week_seconds(day * DAY + time.raw)
     }
 
-    pub fn offset(&self, offset: u64) -> Timestamp {
-        Timestamp {
-            day: self.day,
-            time: self.time.offset(offset),
+    /// Timestamp for a time on a concrete date, for a searcher built for the
+    /// week starting at `week_start`. `None` if the date is outside that week.
+    pub fn on_date(date: Date, time: DayTime, week_start: Date) -> Option<Timestamp> {
+        if week_start <= date && date < week_start.offset(7) {
+            Some(Timestamp::new(date.weekday(), time))
+        } else {
+            None
+        }
+    }
+
+    pub fn day(&self) -> Day {
+        DAYS[(self.seconds / DAY) as usize]
+    }
+
+    pub fn time(&self) -> DayTime {
+        DayTime {
+            raw: self.seconds % DAY,
         }
     }
 
+    pub fn offset(&self, offset: u64) -> Timestamp {
+        Timestamp::from_week_seconds(self.seconds + offset % WEEK)
+    }
+
     pub fn neg_offset(&self, offset: u64) -> Timestamp {
+        Timestamp::from_week_seconds(self.seconds + WEEK - offset % WEEK)
+    }
+
+    /// Seconds from this timestamp until `other`, wrapping around the end of
+    /// the week.
+    pub fn seconds_until(&self, other: Timestamp) -> u64 {
+        (other.seconds + WEEK - self.seconds) % WEEK
+    }
+
+    /// Seconds since the start of the week, Monday midnight.
+    pub fn week_seconds(&self) -> u64 {
+        self.seconds
+    }
+
+    /// Inverse of `week_seconds`, wrapping around the end of the week.
+    pub fn from_week_seconds(seconds: u64) -> Timestamp {
         Timestamp {
-            day: self.day,
-            time: self.time.neg_offset(offset),
+            seconds: seconds % WEEK,
         }
     }
 
+    /// Orders timestamps by how long after `departure` they come.
     pub fn compare_using_departure(&self, other: Timestamp, departure: Timestamp) -> Ordering {
-        if *self == other {
-            Ordering::Equal
-        } else if *self == departure {
-            Ordering::Less
-        } else if other == departure {
-            Ordering::Greater
-        } else if *self < other && other < departure {
-            Ordering::Less
-        } else if other < departure && departure < *self {
-            Ordering::Less
-        } else if departure < *self && *self < other {
-            Ordering::Less
-        } else {
-            Ordering::Greater
-        }
+        departure
+            .seconds_until(*self)
+            .cmp(&departure.seconds_until(other))
     }
 
-    // Returns if other timestamp is ahead of this one, but at most a few days.
+    /// Returns if other timestamp is ahead of this one, but at most
+    /// `MAX_WAIT` seconds.
     pub fn is_followed_by(&self, other: Timestamp) -> bool {
-        let departure_day = match self.day {
-            Day::Monday => Day::Wednesday,
-            Day::Tuesday => Day::Thursday,
-            Day::Wednesday => Day::Friday,
-            Day::Thursday => Day::Saturday,
-            Day::Friday => Day::Sunday,
-            Day::Saturday => Day::Monday,
-            Day::Sunday => Day::Tuesday,
-        };
-        let departure = Timestamp::new(departure_day, DayTime::new(0, 0));
-        self.compare_using_departure(other, departure) != Ordering::Greater
+        self.seconds_until(other) <= MAX_WAIT
+    }
+}
+
+const DAY: u64 = 24 * 3600;
+const WEEK: u64 = 7 * DAY;
+// Longest wait for a vehicle to leave.
+pub const MAX_WAIT: u64 = 2 * DAY;
+
+impl Serialize for Timestamp {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let raw = self.time().raw;
+        let time = format!("{:02}:{:02}:{:02}", raw / 3600, raw / 60 % 60, raw % 60);
+        let mut state = serializer.serialize_struct("Timestamp", 2)?;
+        state.serialize_field("day", &self.day())?;
+        state.serialize_field("time", &time)?;
+        state.end()
     }
 }
 
 impl fmt::Display for Timestamp {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{} {}", self.day, self.time)
+        // unlike `DayTime`, midnight starts the day rather than ending it
+        let raw = self.time().raw;
+        write!(f, "{} {:02}:{:02}", self.day(), raw / 3600, raw / 60 % 60)
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct Route<'a> {
     pub segments: Vec<Segment<'a>>,
-    pub departure_time: DayTime,
-    pub arrival_time: DayTime,
+    pub departure_time: Timestamp,
+    pub arrival_time: Timestamp,
 }
 
-#[derive(Debug, Copy, Clone)]
+impl<'a> Route<'a> {
+    /// Total travel time, in seconds.
+    pub fn duration(&self) -> u64 {
+        self.departure_time.seconds_until(self.arrival_time)
+    }
+
+    /// Number of times the passenger changes vehicles.
+    pub fn transfers(&self) -> usize {
+        let rides = self
+            .segments
+            .iter()
+            .filter(|segment| match **segment {
+                Segment::Bus(_) => true,
+                Segment::Walk(_) => false,
+            })
+            .count();
+        rides.saturating_sub(1)
+    }
+
+    /// Total walked distance, in meters.
+    pub fn walk_distance(&self) -> f64 {
+        self.segments
+            .iter()
+            .map(|segment| match *segment {
+                Segment::Walk(ref walk) => walk.distance,
+                Segment::Bus(_) => 0.0,
+            })
+            .sum()
+    }
+}
+
+impl<'a> Serialize for Route<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("Route", 6)?;
+        state.serialize_field("departure", &self.departure_time)?;
+        state.serialize_field("arrival", &self.arrival_time)?;
+        state.serialize_field("duration", &self.duration())?;
+        state.serialize_field("transfers", &self.transfers())?;
+        state.serialize_field("walk_distance", &self.walk_distance())?;
+        state.serialize_field("segments", &self.segments)?;
+        state.end()
+    }
+}
+
+#[derive(Serialize, Debug, Copy, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
 pub enum Segment<'a> {
     Walk(WalkSegment<'a>),
     Bus(BusSegment<'a>),
@@ -345,14 +717,32 @@ pub enum Segment<'a> {
 pub struct WalkSegment<'a> {
     pub from: NamedPoint<'a>,
     pub to: NamedPoint<'a>,
-    pub start: DayTime,
+    pub start: Timestamp,
     pub duration: u64,
+    /// Walked distance, in meters.
+    pub distance: f64,
 }
 
-#[derive(Debug, Copy, Clone)]
+impl<'a> Serialize for WalkSegment<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("WalkSegment", 6)?;
+        state.serialize_field("from", &self.from)?;
+        state.serialize_field("to", &self.to)?;
+        state.serialize_field("start", &self.start)?;
+        state.serialize_field("end", &self.start.offset(self.duration))?;
+        state.serialize_field("duration", &self.duration)?;
+        state.serialize_field("distance", &self.distance)?;
+        state.end()
+    }
+}
+
+/// A location in a route, either a stop or an arbitrary point.
+#[derive(Serialize, Debug, Copy, Clone)]
 pub struct NamedPoint<'a> {
-    pub loc: Point,
+    pub id: Option<&'a str>,
     pub name: Option<&'a str>,
+    #[serde(flatten)]
+    pub loc: Point,
 }
 
 impl<'a> fmt::Display for NamedPoint<'a> {
@@ -365,16 +755,62 @@ impl<'a> fmt::Display for NamedPoint<'a> {
     }
 }
 
+/// Identifies a single vehicle run: one departure of a timetable in a track
+/// of a schedule, on a given day.
+#[derive(PartialEq, Eq, Hash, Debug, Copy, Clone)]
+pub struct TripId {
+    /// Index of the schedule in the list the searcher was built from.
+    pub schedule: usize,
+    /// Index of the track in `Schedule::tracks`.
+    pub track: usize,
+    /// Index of the timetable in `Track::timetables`.
+    pub timetable: usize,
+    /// Index of the departure in `Timetable::departure_times`.
+    pub departure: usize,
+    pub day: Day,
+}
+
 #[derive(Debug, Copy, Clone)]
 pub struct BusSegment<'a> {
     pub bus: &'a str,
+    pub trip: TripId,
     pub typ: TransportType,
-    pub from_stop: &'a str,
-    pub to_stop: &'a str,
-    pub start: DayTime,
+    pub from: NamedPoint<'a>,
+    pub to: NamedPoint<'a>,
+    pub start: Timestamp,
     pub duration: u64,
 }
 
+impl<'a> Serialize for BusSegment<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("BusSegment", 7)?;
+        state.serialize_field("line", self.bus)?;
+        state.serialize_field("transport", &self.typ)?;
+        state.serialize_field("from", &self.from)?;
+        state.serialize_field("to", &self.to)?;
+        state.serialize_field("start", &self.start)?;
+        state.serialize_field("end", &self.start.offset(self.duration))?;
+        state.serialize_field("duration", &self.duration)?;
+        state.end()
+    }
+}
+
+impl<'a> Segment<'a> {
+    pub fn start(&self) -> Timestamp {
+        match *self {
+            Segment::Walk(ref seg) => seg.start,
+            Segment::Bus(ref seg) => seg.start,
+        }
+    }
+
+    pub fn end(&self) -> Timestamp {
+        match *self {
+            Segment::Walk(ref seg) => seg.start.offset(seg.duration),
+            Segment::Bus(ref seg) => seg.start.offset(seg.duration),
+        }
+    }
+}
+
 impl<'a> fmt::Display for Segment<'a> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
@@ -389,7 +825,7 @@ impl<'a> fmt::Display for WalkSegment<'a> {
         write!(
             f,
             "At {} - walk from {} to {}, walking time: {} minutes",
-            self.start,
+            self.start.time(),
             self.from,
             self.to,
             (self.duration + 30) / 60,
@@ -402,11 +838,11 @@ impl<'a> fmt::Display for BusSegment<'a> {
         write!(
             f,
             "At {} - take {} {} from {} to {}, ride time: {} minutes",
-            self.start,
+            self.start.time(),
             self.typ,
             self.bus,
-            self.from_stop,
-            self.to_stop,
+            self.from,
+            self.to,
             (self.duration + 30) / 60,
         )
     }
@@ -430,4 +866,206 @@ mod tests {
         let distance = p1.distance(p2);
         assert!((distance - 1960.0).abs() < 5.0);
     }
+
+    #[test]
+    fn timetable_days() {
+        let timetable = Timetable {
+            // Monday to Friday
+            days: 0b0111110,
+            departures: Vec::new(),
+            durations: Vec::new(),
+            added_dates: Vec::new(),
+            removed_dates: vec![Date::new(2018, 7, 9)],
+            valid_dates: None,
+        };
+        assert!(timetable.works_on_day(Day::Monday));
+        assert!(timetable.works_on_day(Day::Friday));
+        assert!(!timetable.works_on_day(Day::Saturday));
+        assert!(!timetable.works_on_day(Day::Sunday));
+
+        let mut calendar = Calendar::default();
+        // Friday, Statehood Day
+        let holiday = Date::new(2018, 7, 6);
+        calendar.overrides.insert(holiday, Day::Sunday);
+        assert!(timetable.works_on_date(Date::new(2018, 7, 5), &calendar));
+        assert!(!timetable.works_on_date(holiday, &calendar));
+        assert!(!timetable.works_on_date(Date::new(2018, 7, 9), &calendar));
+    }
+
+    #[test]
+    fn timetable_dates_from_json() {
+        let json = r#"{
+            "Days": 62,
+            "Departures": [],
+            "StopDurations": [],
+            "AddedDates": ["2018-07-07"],
+            "RemovedDates": ["20180706"]
+        }"#;
+        let timetable: Timetable = ::serde_json::from_str(json).unwrap();
+        assert_eq!(timetable.added_dates, vec![Date::new(2018, 7, 7)]);
+        assert_eq!(timetable.removed_dates, vec![Date::new(2018, 7, 6)]);
+        let json = r#"{"Days": 62, "Departures": [], "StopDurations": []}"#;
+        let timetable: Timetable = ::serde_json::from_str(json).unwrap();
+        assert!(timetable.added_dates.is_empty() && timetable.removed_dates.is_empty());
+    }
+
+    #[test]
+    fn dates() {
+        let date = "2018-07-03".parse::<Date>().unwrap();
+        assert_eq!(date, Date::new(2018, 7, 3));
+        assert_eq!("20180703".parse::<Date>(), Ok(date));
+        assert!("2018-02-29".parse::<Date>().is_err());
+        assert_eq!(date.weekday(), Day::Tuesday);
+        assert_eq!(date.offset(-3), Date::new(2018, 6, 30));
+        assert_eq!(Date::new(2016, 12, 31).offset(60), Date::new(2017, 3, 1));
+        assert_eq!(Date::new(2000, 2, 29).weekday(), Day::Tuesday);
+        assert_eq!(date.to_string(), "2018-07-03");
+    }
+
+    #[test]
+    fn parse_day_times() {
+        assert_eq!("23:10".parse::<DayTime>(), Ok(DayTime::new(23, 10)));
+        assert_eq!("7:05".parse::<DayTime>(), Ok(DayTime::new(7, 5)));
+        assert!("24:00".parse::<DayTime>().is_err());
+        assert!("2310".parse::<DayTime>().is_err());
+    }
+
+    #[test]
+    fn parse_days() {
+        assert_eq!("Tue".parse::<Day>(), Ok(Day::Tuesday));
+        assert_eq!("sunday".parse::<Day>(), Ok(Day::Sunday));
+        assert!("S".parse::<Day>().is_err());
+        assert!("Tuesdays".parse::<Day>().is_err());
+    }
+
+    #[test]
+    fn timestamp_arithmetic() {
+        let monday = Timestamp::new(Day::Monday, DayTime::new(0, 5));
+        let sunday = Timestamp::new(Day::Sunday, DayTime::new(23, 55));
+        assert_eq!(monday.neg_offset(10 * 60), sunday);
+        assert_eq!(sunday.seconds_until(monday), 10 * 60);
+        assert_eq!(monday.seconds_until(sunday), 7 * 24 * 3600 - 10 * 60);
+    }
+
+    #[test]
+    fn timestamps_past_midnight() {
+        let late = Timestamp::new(Day::Tuesday, DayTime::new(23, 50));
+        let wednesday = Timestamp::new(Day::Wednesday, DayTime::new(0, 10));
+        assert_eq!(late.offset(20 * 60), wednesday);
+        assert_eq!(
+            Timestamp::new(
+                Day::Tuesday,
+                DayTime {
+                    raw: 24 * 3600 + 600
+                }
+            ),
+            wednesday
+        );
+        assert_eq!(wednesday.day(), Day::Wednesday);
+        assert_eq!(wednesday.time(), DayTime::new(0, 10));
+        assert_eq!(late.offset(10 * 60).to_string(), "Wed 00:00");
+
+        let sunday = Timestamp::new(Day::Sunday, DayTime::new(23, 50));
+        let monday = Timestamp::new(Day::Monday, DayTime::new(0, 10));
+        assert_eq!(sunday.offset(20 * 60), monday);
+        assert!(sunday.is_followed_by(monday));
+        assert!(!monday.is_followed_by(sunday));
+        assert_eq!(
+            monday.compare_using_departure(sunday, sunday),
+            Ordering::Greater
+        );
+        assert_eq!(
+            ::serde_json::to_value(monday).unwrap(),
+            json!({ "day": "Mon", "time": "00:10:00" })
+        );
+    }
+
+    #[test]
+    fn serialize_route() {
+        let stop = NamedPoint {
+            id: Some("vln_0101"),
+            name: Some("Žaliasis tiltas"),
+            loc: Point {
+                lat: 54.69236,
+                lng: 25.28048,
+            },
+        };
+        let start = Timestamp::new(Day::Tuesday, DayTime::new(23, 50));
+        let route = Route {
+            segments: vec![Segment::Walk(WalkSegment {
+                from: NamedPoint {
+                    id: None,
+                    name: None,
+                    loc: Point {
+                        lat: 54.69,
+                        lng: 25.28,
+                    },
+                },
+                to: stop,
+                start,
+                duration: 90,
+                distance: 100.0,
+            })],
+            departure_time: start,
+            arrival_time: start.offset(90),
+        };
+        let json = ::serde_json::to_value(&route).unwrap();
+        assert_eq!(
+            json,
+            json!({
+                "departure": { "day": "Tue", "time": "23:50:00" },
+                "arrival": { "day": "Tue", "time": "23:51:30" },
+                "duration": 90,
+                "transfers": 0,
+                "walk_distance": 100.0,
+                "segments": [{
+                    "type": "walk",
+                    "from": { "id": null, "name": null, "lat": 54.69, "lng": 25.28 },
+                    "to": {
+                        "id": "vln_0101",
+                        "name": "Žaliasis tiltas",
+                        "lat": 54.69236,
+                        "lng": 25.28048,
+                    },
+                    "start": { "day": "Tue", "time": "23:50:00" },
+                    "end": { "day": "Tue", "time": "23:51:30" },
+                    "duration": 90,
+                    "distance": 100.0,
+                }],
+            })
+        );
+    }
+
+    #[test]
+    fn periodic_times() {
+        let periodic = Periodic {
+            from: DayTime::new(7, 0),
+            to: DayTime::new(8, 0),
+            headway: 20 * 60,
+        };
+        let times = periodic.times().collect::<Vec<_>>();
+        assert_eq!(
+            times,
+            vec![DayTime::new(7, 0), DayTime::new(7, 20), DayTime::new(7, 40)]
+        );
+    }
+
+    #[test]
+    fn departures_with_and_without_headway() {
+        let json = r#"[
+            {"Time": 25200},
+            {"FromTime": 25200, "ToTime": 28800},
+            {"FromTime": 25200, "ToTime": 28800, "Headway": 1200}
+        ]"#;
+        let departures: Vec<Departure> = ::serde_json::from_str(json).unwrap();
+        let times = departures.iter().map(|d| d.times()).collect::<Vec<_>>();
+        assert_eq!(
+            times,
+            vec![
+                vec![DayTime::new(7, 0)],
+                vec![],
+                vec![DayTime::new(7, 0), DayTime::new(7, 20), DayTime::new(7, 40)],
+            ]
+        );
+    }
 }
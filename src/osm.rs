@@ -0,0 +1,186 @@
+use itertools::Itertools;
+use model::{Point, Streets};
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use Res;
+
+// Highways pedestrians may not use.
+const NOT_WALKABLE: &[&str] = &[
+    "motorway",
+    "motorway_link",
+    "trunk",
+    "trunk_link",
+    "raceway",
+    "bus_guideway",
+    "construction",
+    "proposed",
+];
+
+/// Loads streets one can walk along from an `.osm` XML file.
+pub fn load<P: AsRef<Path>>(path: P) -> Res<Streets> {
+    parse(BufReader::new(File::open(path)?))
+}
+
+#[derive(Default)]
+struct Way {
+    nodes: Vec<i64>,
+    tags: HashMap<String, String>,
+}
+
+impl Way {
+    fn is_walkable(&self) -> bool {
+        let tag = |key: &str| self.tags.get(key).map(String::as_str);
+        let highway = match tag("highway") {
+            Some(highway) => highway,
+            None => return false,
+        };
+        if NOT_WALKABLE.contains(&highway) {
+            return false;
+        }
+        match tag("foot") {
+            Some("no") | Some("private") => false,
+            Some(_) => true,
+            None => tag("access") != Some("no") && tag("access") != Some("private"),
+        }
+    }
+}
+
+fn parse<R: BufRead>(input: R) -> Res<Streets> {
+    let mut reader = Reader::from_reader(input);
+    reader.trim_text(true);
+    let mut buf = Vec::new();
+    let mut locations = HashMap::<i64, Point>::new();
+    let mut ways = Vec::new();
+    let mut way: Option<Way> = None;
+    loop {
+        buf.clear();
+        let (element, is_empty) = match reader.read_event_into(&mut buf)? {
+            Event::Start(element) => (element, false),
+            Event::Empty(element) => (element, true),
+            Event::End(ref element) if element.name().as_ref() == b"way" => {
+                ways.extend(way.take());
+                continue;
+            }
+            Event::Eof => break,
+            _ => continue,
+        };
+        let mut attributes = attributes(&element)?;
+        let mut attribute = |name: &str| {
+            attributes
+                .remove(name)
+                .ok_or_else(|| format!("OSM element is missing attribute {}", name))
+        };
+        match element.name().as_ref() {
+            b"node" => {
+                let id = attribute("id")?.parse()?;
+                let lat = attribute("lat")?.parse()?;
+                let lng = attribute("lon")?.parse()?;
+                locations.insert(id, Point { lat, lng });
+            }
+            b"way" if !is_empty => way = Some(Way::default()),
+            b"nd" => {
+                if let Some(ref mut way) = way {
+                    way.nodes.push(attribute("ref")?.parse()?);
+                }
+            }
+            b"tag" => {
+                if let Some(ref mut way) = way {
+                    way.tags.insert(attribute("k")?, attribute("v")?);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut nodes = Vec::new();
+    let mut indices = HashMap::new();
+    let mut index = |id: i64, loc: Point| {
+        *indices.entry(id).or_insert_with(|| {
+            nodes.push(loc);
+            nodes.len() - 1
+        })
+    };
+    let mut edges = Vec::new();
+    for way in ways.iter().filter(|way| way.is_walkable()) {
+        for (a, b) in way.nodes.iter().tuple_windows() {
+            // extracts cut ways at their boundary, leaving nodes out
+            if let (Some(&a_loc), Some(&b_loc)) = (locations.get(a), locations.get(b)) {
+                edges.push((index(*a, a_loc), index(*b, b_loc)));
+            }
+        }
+    }
+    let streets = Streets { nodes, edges };
+    debug!(
+        "Read {} OSM ways with {} nodes",
+        ways.len(),
+        locations.len()
+    );
+    debug!(
+        "Built street network: {} nodes, {} edges",
+        streets.nodes.len(),
+        streets.edges.len()
+    );
+    Ok(streets)
+}
+
+fn attributes(element: &BytesStart) -> Res<HashMap<String, String>> {
+    let mut attributes = HashMap::new();
+    for attribute in element.attributes() {
+        let attribute = attribute?;
+        let key = String::from_utf8_lossy(attribute.key.as_ref()).into_owned();
+        attributes.insert(key, attribute.unescape_value()?.into_owned());
+    }
+    Ok(attributes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXTRACT: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+  <node id="1" lat="54.700" lon="25.200"/>
+  <node id="2" lat="54.701" lon="25.200"/>
+  <node id="3" lat="54.702" lon="25.200">
+    <tag k="highway" v="crossing"/>
+  </node>
+  <node id="4" lat="54.703" lon="25.200"/>
+  <way id="10">
+    <nd ref="1"/>
+    <nd ref="2"/>
+    <nd ref="3"/>
+    <tag k="highway" v="residential"/>
+  </way>
+  <way id="11">
+    <nd ref="3"/>
+    <nd ref="4"/>
+    <tag k="highway" v="motorway"/>
+  </way>
+  <way id="12">
+    <nd ref="3"/>
+    <nd ref="99"/>
+    <nd ref="4"/>
+    <tag k="highway" v="footway"/>
+  </way>
+  <way id="13">
+    <nd ref="1"/>
+    <nd ref="4"/>
+    <tag k="highway" v="service"/>
+    <tag k="access" v="private"/>
+  </way>
+</osm>
+"#;
+
+    #[test]
+    fn parse_walkable_ways() {
+        let streets = parse(EXTRACT.as_bytes()).unwrap();
+        assert_eq!(streets.nodes.len(), 3);
+        // the motorway, the private road and the footway missing a node
+        // are not walkable
+        assert_eq!(streets.edges, vec![(0, 1), (1, 2)]);
+    }
+}
@@ -0,0 +1,197 @@
+use super::{SearchOptions, Searcher};
+use model::{Point, Route, Segment, Timestamp};
+use std::collections::{HashSet, VecDeque};
+
+// Upper bound on searches done for a single query.
+const MAX_SEARCHES: usize = 30;
+// Routes sharing a larger part of their rides with another one are not
+// shown.
+const MAX_SIMILARITY: f64 = 0.5;
+// Alternatives taking longer than twice the best route plus this much, in
+// seconds, are not worth showing.
+const SLACK: u64 = 15 * 60;
+
+impl Searcher {
+    /// Finds up to `count` routes from `from` to `to` that ride mostly
+    /// different lines. The best route comes first, others are sorted by
+    /// arrival time.
+    pub fn find_alternatives(
+        &self,
+        from: Point,
+        to: Point,
+        departure: Timestamp,
+        count: usize,
+        options: &SearchOptions,
+    ) -> Vec<Route<'_>> {
+        let mut routes = Vec::<Route>::new();
+        let mut queue = VecDeque::new();
+        let mut seen = HashSet::new();
+        queue.push_back(Vec::<&str>::new());
+
+        let mut searches = 0;
+        while let Some(banned) = queue.pop_front() {
+            if routes.len() >= count || searches >= MAX_SEARCHES {
+                break;
+            }
+            searches += 1;
+            let mut options = options.clone();
+            options
+                .banned_lines
+                .extend(banned.iter().map(|&line| line.to_owned()));
+            let route = match self.find_route(from, to, departure, &options) {
+                Some(route) => route,
+                None => continue,
+            };
+            if let Some(best) = routes.first() {
+                if route.duration() > 2 * best.duration() + SLACK {
+                    continue;
+                }
+            }
+
+            for line in lines(&route) {
+                let mut banned = banned.clone();
+                banned.push(line);
+                banned.sort();
+                if seen.insert(banned.clone()) {
+                    queue.push_back(banned);
+                }
+            }
+            let is_distinct = routes
+                .iter()
+                .all(|other| similarity(other, &route) < MAX_SIMILARITY);
+            if is_distinct {
+                routes.push(route);
+            }
+        }
+
+        if routes.len() > 1 {
+            routes[1..].sort_by_key(|route| departure.seconds_until(route.arrival_time));
+        }
+        debug!(
+            "Found {} alternatives in {} searches",
+            routes.len(),
+            searches
+        );
+        routes
+    }
+}
+
+/// Names of lines a route rides, in order.
+fn lines<'a>(route: &Route<'a>) -> Vec<&'a str> {
+    route
+        .segments
+        .iter()
+        .filter_map(|segment| match *segment {
+            Segment::Bus(ref bus) => Some(bus.bus),
+            Segment::Walk(_) => None,
+        })
+        .collect()
+}
+
+/// Line, boarding stop and alighting stop of each ride of a route, in order.
+fn legs<'a>(route: &Route<'a>) -> Vec<(&'a str, Option<&'a str>, Option<&'a str>)> {
+    route
+        .segments
+        .iter()
+        .filter_map(|segment| match *segment {
+            Segment::Bus(ref bus) => Some((bus.bus, bus.from.id, bus.to.id)),
+            Segment::Walk(_) => None,
+        })
+        .collect()
+}
+
+/// Share of rides that both routes take in the same order, from 0 for
+/// completely different routes to 1 for routes riding the same lines between
+/// the same stops.
+fn similarity(a: &Route, b: &Route) -> f64 {
+    let (a, b) = (legs(a), legs(b));
+    let longest = a.len().max(b.len());
+    if longest == 0 {
+        return 1.0;
+    }
+    // longest common subsequence of rides
+    let mut common = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for i in 0..a.len() {
+        for j in 0..b.len() {
+            common[i + 1][j + 1] = if a[i] == b[j] {
+                common[i][j] + 1
+            } else {
+                common[i][j + 1].max(common[i + 1][j])
+            };
+        }
+    }
+    common[a.len()][b.len()] as f64 / longest as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{ends, schedule, stop, track, transfer_network};
+    use super::*;
+    use model::{Day, DayTime};
+
+    #[test]
+    fn alternatives_use_different_lines() {
+        let (stops, mut schedules) = transfer_network(1200);
+        // same as line 3, but later
+        schedules.push(schedule(
+            "4",
+            vec![track("D", &["a", "c"], &[DayTime::new(10, 30)], 1200)],
+        ));
+        let searcher = Searcher::new(stops, schedules);
+        let (from, to) = ends();
+        let departure = Timestamp::new(Day::Tuesday, DayTime::new(9, 55));
+        let options = SearchOptions::default();
+        let routes = searcher.find_alternatives(from, to, departure, 5, &options);
+
+        let used = routes.iter().map(lines).collect::<Vec<_>>();
+        assert_eq!(used, vec![vec!["1", "2"], vec!["3"], vec!["4"]]);
+
+        let routes = searcher.find_alternatives(from, to, departure, 2, &options);
+        assert_eq!(routes.len(), 2);
+    }
+
+    #[test]
+    fn alternative_boarding_elsewhere_is_kept() {
+        let stops = vec![
+            stop("x", 54.70, 25.200),
+            stop("a", 54.72, 25.200),
+            // a short walk from x
+            stop("b", 54.70, 25.204),
+            stop("c", 54.74, 25.200),
+        ];
+        let schedules = vec![
+            schedule(
+                "2",
+                vec![track("A", &["x", "a"], &[DayTime::new(10, 0)], 300)],
+            ),
+            schedule(
+                "1",
+                vec![
+                    track("A", &["a", "c"], &[DayTime::new(10, 10)], 600),
+                    track("B", &["b", "c"], &[DayTime::new(10, 20)], 600),
+                ],
+            ),
+        ];
+        let searcher = Searcher::new(stops, schedules);
+        let from = Point {
+            lat: 54.70,
+            lng: 25.20,
+        };
+        let to = Point {
+            lat: 54.74,
+            lng: 25.20,
+        };
+        let departure = Timestamp::new(Day::Tuesday, DayTime::new(9, 55));
+        let options = SearchOptions::default();
+        let routes = searcher.find_alternatives(from, to, departure, 5, &options);
+
+        let used = routes.iter().map(legs).collect::<Vec<_>>();
+        assert_eq!(
+            used,
+            vec![
+                vec![("2", Some("x"), Some("a")), ("1", Some("a"), Some("c"))],
+                vec![("1", Some("b"), Some("c"))],
+            ]
+        );
+    }
+}
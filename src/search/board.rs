@@ -0,0 +1,142 @@
+use super::{Searcher, StopRef, StopRoute};
+use model::{Timestamp, TransportType};
+
+/// A vehicle leaving a stop.
+#[derive(Serialize, Debug, Clone)]
+pub struct StopDeparture<'a> {
+    /// The platform it leaves from.
+    pub stop: StopRef<'a>,
+    pub line: &'a str,
+    #[serde(rename = "transport")]
+    pub typ: TransportType,
+    /// Name of the track, usually its destination.
+    pub track: &'a str,
+    pub time: Timestamp,
+    /// Stops it reaches afterwards, in order.
+    pub following: Vec<StopTime<'a>>,
+}
+
+/// When a vehicle reaches a stop.
+#[derive(Serialize, Debug, Clone)]
+pub struct StopTime<'a> {
+    #[serde(flatten)]
+    pub stop: StopRef<'a>,
+    pub time: Timestamp,
+}
+
+impl Searcher {
+    /// The next `count` departures at or after `time` from a stop given by
+    /// id, or from all platforms with the given name. `None` if there is no
+    /// such stop.
+    pub fn departures(
+        &self,
+        stop: &str,
+        time: Timestamp,
+        count: usize,
+    ) -> Option<Vec<StopDeparture<'_>>> {
+        let platforms = self.platforms(stop);
+        if platforms.is_empty() {
+            return None;
+        }
+        let mut departures = platforms
+            .into_iter()
+            .flat_map(|id| {
+                let routes = &self.stops[id].routes;
+                let start = routes.partition_point(|route| route.departure < time);
+                let (earlier, later) = routes.split_at(start);
+                later
+                    .iter()
+                    .chain(earlier)
+                    .take(count)
+                    .map(move |route| (id, route))
+            })
+            .collect::<Vec<_>>();
+        departures.sort_by_key(|&(id, route)| (time.seconds_until(route.departure), id));
+        departures.truncate(count);
+        Some(
+            departures
+                .into_iter()
+                .map(|(id, route)| self.departure(id, route))
+                .collect(),
+        )
+    }
+
+    fn departure<'a>(&'a self, id: &'a str, route: &'a StopRoute) -> StopDeparture<'a> {
+        let trip = route.trip;
+        StopDeparture {
+            stop: self.stop_ref(id),
+            line: &route.bus,
+            typ: route.typ,
+            track: &self.tracks[trip.schedule][trip.track].name,
+            time: route.departure,
+            following: self.trip_stops(trip, route.index + 1),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{named_stop, schedule, stop, track};
+    use super::*;
+    use model::{Day, DayTime};
+
+    #[test]
+    fn next_departures_from_all_platforms() {
+        let stops = vec![
+            named_stop("a1", "Centras", 54.70, 25.20),
+            named_stop("a2", "Centras", 54.70, 25.21),
+            stop("b", 54.71, 25.20),
+            stop("c", 54.72, 25.20),
+        ];
+        let schedules = vec![
+            schedule(
+                "1",
+                vec![track(
+                    "To c",
+                    &["a1", "b", "c"],
+                    &[DayTime::new(8, 0), DayTime::new(23, 50)],
+                    600,
+                )],
+            ),
+            schedule(
+                "2",
+                vec![track("To b", &["a2", "b"], &[DayTime::new(8, 5)], 300)],
+            ),
+        ];
+        let searcher = Searcher::new(stops, schedules);
+        let time = Timestamp::new(Day::Tuesday, DayTime::new(8, 1));
+
+        let board = searcher.departures("centras", time, 3).unwrap();
+        let summary = board
+            .iter()
+            .map(|departure| {
+                (
+                    departure.stop.id,
+                    departure.line,
+                    departure.time.to_string(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                ("a2", "2", "Tue 08:05".to_owned()),
+                ("a1", "1", "Tue 23:50".to_owned()),
+                ("a1", "1", "Wed 08:00".to_owned()),
+            ]
+        );
+        let following = board[1]
+            .following
+            .iter()
+            .map(|stop| (stop.stop.id, stop.time.to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            following,
+            vec![("b", "Wed 00:00".to_owned()), ("c", "Wed 00:10".to_owned())]
+        );
+        assert_eq!(board[1].track, "To c");
+
+        assert_eq!(searcher.departures("a2", time, 10).unwrap().len(), 7);
+        assert!(searcher.departures("nowhere", time, 3).is_none());
+    }
+}
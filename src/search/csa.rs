@@ -0,0 +1,643 @@
+use super::walking::Walking;
+use super::{
+    footpaths, post_process_route, Engine, FootpathOptions, SearchOptions, Service, DAY, WEEK,
+};
+use model::{
+    BusSegment, Calendar, Date, NamedPoint, Point, Route, Schedule, Segment, Stop as MStop,
+    Timestamp, TransportType, TripId, WalkSegment, DAYS,
+};
+use std::collections::HashMap;
+
+// Profile queries consider journeys arriving at most this long after the
+// latest departure, in seconds.
+const MAX_TRAVEL: u64 = DAY;
+
+/// Searcher using the Connection Scan Algorithm, built from the same data
+/// as `Searcher`. Every ride between two consecutive stops is a connection,
+/// scanned in order of departure.
+pub struct ConnectionScan {
+    stops: Vec<Stop>,
+    trips: Vec<Trip>,
+    /// Sorted by departure.
+    connections: Vec<Connection>,
+    /// Indices of stops, by walking distance.
+    walking: Walking<usize>,
+}
+
+struct Stop {
+    id: String,
+    name: String,
+    loc: Point,
+    /// Nearby stops with distances to them.
+    footpaths: Vec<(usize, f64)>,
+}
+
+struct Trip {
+    id: TripId,
+    bus: String,
+    typ: TransportType,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Connection {
+    from: usize,
+    to: usize,
+    /// Seconds since the start of the week.
+    departure: u64,
+    arrival: u64,
+    trip: usize,
+}
+
+#[derive(Debug, Copy, Clone)]
+enum Parent {
+    /// Walked from the origin.
+    Access { distance: f64 },
+    /// Rode from the first to the second connection of a trip.
+    Ride { boarded: usize, alighted: usize },
+    /// Walked from the stop where a ride, given as for `Ride`, ended.
+    Walk {
+        boarded: usize,
+        alighted: usize,
+        distance: f64,
+    },
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Label {
+    arrival: u64,
+    rides: u64,
+    walked: f64,
+    parent: Parent,
+}
+
+impl Label {
+    fn cost(&self, options: &SearchOptions) -> u64 {
+        self.arrival + options.transfer_penalty * self.rides
+    }
+}
+
+/// State of a trip during a forward scan.
+#[derive(Debug, Copy, Clone)]
+struct Boarding {
+    connection: usize,
+    /// Label of the stop the trip was boarded from, which later labels of
+    /// that stop may replace.
+    label: Label,
+}
+
+/// Best way to finish found by a forward scan.
+#[derive(Debug, Copy, Clone)]
+struct Finish {
+    cost: u64,
+    arrival: u64,
+    stop: usize,
+    distance: f64,
+    /// Label of `stop` when the destination was reached from it.
+    label: Label,
+}
+
+impl ConnectionScan {
+    pub fn new(stops: Vec<MStop>, schedules: Vec<Schedule>) -> ConnectionScan {
+        ConnectionScan::build(
+            stops,
+            schedules,
+            Service::Weekly,
+            &FootpathOptions::default(),
+        )
+    }
+
+    /// Builds a searcher for the week starting at `week_start`, like
+    /// `Searcher::with_calendar`.
+    pub fn with_calendar(
+        stops: Vec<MStop>,
+        schedules: Vec<Schedule>,
+        calendar: &Calendar,
+        week_start: Date,
+    ) -> ConnectionScan {
+        ConnectionScan::build(
+            stops,
+            schedules,
+            Service::Dated {
+                calendar,
+                week_start,
+            },
+            &FootpathOptions::default(),
+        )
+    }
+
+    /// Builds a searcher running timetables by `service`, with walking
+    /// connections found using `footpaths`, like `Searcher::build`.
+    pub fn build(
+        stops: Vec<MStop>,
+        schedules: Vec<Schedule>,
+        service: Service,
+        footpaths: &FootpathOptions,
+    ) -> ConnectionScan {
+        let stops = stops
+            .into_iter()
+            .map(|stop| Stop {
+                id: stop.id,
+                name: stop.name,
+                loc: stop.loc,
+                footpaths: Vec::new(),
+            })
+            .collect::<Vec<_>>();
+        let ids = stops
+            .iter()
+            .enumerate()
+            .map(|(index, stop)| (stop.id.as_str(), index))
+            .collect::<HashMap<_, _>>();
+
+        let mut trips = Vec::new();
+        let mut connections = Vec::new();
+        for (schedule_index, schedule) in schedules.iter().enumerate() {
+            for (track_index, track) in schedule.tracks.iter().enumerate() {
+                let track_stops = track
+                    .stops
+                    .iter()
+                    .map(|id| {
+                        *ids.get(id.as_str())
+                            .expect("schedule refers to non-existing stop")
+                    })
+                    .collect::<Vec<_>>();
+                for (day_index, &day) in DAYS.iter().enumerate() {
+                    let day_start = day_index as u64 * DAY;
+                    for (tt_index, tt) in track.timetables.iter().enumerate() {
+                        if !service.runs(tt, day) {
+                            continue;
+                        }
+                        for (dep_index, time) in tt.departure_times().enumerate() {
+                            let id = TripId {
+                                schedule: schedule_index,
+                                track: track_index,
+                                timetable: tt_index,
+                                departure: dep_index,
+                                day,
+                            };
+                            // trips late in the week are also caught from the
+                            // start of the next one
+                            for &week in &[0, WEEK] {
+                                let trip = trips.len();
+                                trips.push(Trip {
+                                    id,
+                                    bus: schedule.name.clone(),
+                                    typ: schedule.transport_type,
+                                });
+                                let stop_time =
+                                    |index| week + day_start + tt.find_stop_time(index, time).raw;
+                                for index in 1..track_stops.len() {
+                                    connections.push(Connection {
+                                        from: track_stops[index - 1],
+                                        to: track_stops[index],
+                                        departure: stop_time(index - 1),
+                                        arrival: stop_time(index),
+                                        trip,
+                                    });
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        connections.sort_by_key(|connection| (connection.departure, connection.arrival));
+        debug!(
+            "Built connection scan network: {} stops, {} connections",
+            stops.len(),
+            connections.len()
+        );
+        let mut csa = ConnectionScan {
+            stops,
+            trips,
+            connections,
+            walking: Walking::new(Vec::new(), None),
+        };
+        csa.set_footpaths(footpaths);
+        csa
+    }
+
+    /// Replaces walking connections between stops, like
+    /// `Searcher::with_footpaths`.
+    pub fn with_footpaths(mut self, options: &FootpathOptions) -> ConnectionScan {
+        self.set_footpaths(options);
+        self
+    }
+
+    fn set_footpaths(&mut self, options: &FootpathOptions) {
+        let stops = self.stops.iter().map(|stop| stop.loc).zip(0..).collect();
+        self.walking = Walking::new(stops, options.streets.as_ref());
+        let paths = {
+            let ids = self
+                .stops
+                .iter()
+                .map(|stop| stop.id.as_str())
+                .collect::<Vec<_>>();
+            footpaths(&self.walking, &ids, options)
+        };
+        for (stop, paths) in self.stops.iter_mut().zip(paths) {
+            stop.footpaths = paths;
+        }
+    }
+
+    fn stop_point(&self, stop: usize) -> NamedPoint<'_> {
+        let stop = &self.stops[stop];
+        NamedPoint {
+            id: Some(&stop.id),
+            name: Some(&stop.name),
+            loc: stop.loc,
+        }
+    }
+
+    /// Walking distance from each stop to `to`, if it is close enough.
+    fn egress(&self, to: Point, options: &SearchOptions) -> Vec<Option<f64>> {
+        let mut egress = vec![None; self.stops.len()];
+        for (&stop, distance) in self.walking.within(to, options.max_walk_distance) {
+            egress[stop] = Some(distance);
+        }
+        egress
+    }
+
+    /// Scans connections departing from `start` on, returning how trips
+    /// were boarded and the best way to reach the destination.
+    fn scan(
+        &self,
+        from: Point,
+        start: u64,
+        egress: &[Option<f64>],
+        options: &SearchOptions,
+    ) -> (Vec<Option<Boarding>>, Option<Finish>) {
+        let mut labels = vec![None; self.stops.len()];
+        let mut finish = None;
+        let consider = |finish: &mut Option<Finish>, stop: usize, label: &Label| {
+            let distance = match egress[stop] {
+                Some(distance) if label.walked + distance <= options.max_total_walk => distance,
+                _ => return,
+            };
+            let arrival = label.arrival + options.walk_time(distance);
+            let cost = label.cost(options) + options.walk_time(distance);
+            let is_better = match *finish {
+                Some(ref finish) => cost < finish.cost,
+                None => true,
+            };
+            if is_better {
+                *finish = Some(Finish {
+                    cost,
+                    arrival,
+                    stop,
+                    distance,
+                    label: *label,
+                });
+            }
+        };
+
+        for (&index, distance) in self.walking.within(from, options.max_walk_distance) {
+            if distance > options.max_total_walk {
+                continue;
+            }
+            let label = Label {
+                arrival: start + options.walk_time(distance),
+                rides: 0,
+                walked: distance,
+                parent: Parent::Access { distance },
+            };
+            consider(&mut finish, index, &label);
+            labels[index] = Some(label);
+        }
+
+        let mut boardings: Vec<Option<Boarding>> = vec![None; self.trips.len()];
+        let first = self
+            .connections
+            .partition_point(|connection| connection.departure < start);
+        for (index, connection) in self.connections.iter().enumerate().skip(first) {
+            if let Some(finish) = finish {
+                if connection.departure >= finish.cost {
+                    break;
+                }
+            }
+            if boardings[connection.trip].is_none() {
+                let label: Label = match labels[connection.from] {
+                    Some(label) => label,
+                    None => continue,
+                };
+                let trip = &self.trips[connection.trip];
+                if label.arrival + options.transfer_time > connection.departure
+                    || !options.may_board(label.rides)
+                    || !options.allows(&trip.bus, trip.typ)
+                {
+                    continue;
+                }
+                boardings[connection.trip] = Some(Boarding {
+                    connection: index,
+                    label,
+                });
+            }
+            let boarding = boardings[connection.trip].expect("trip was just boarded");
+            let label = Label {
+                arrival: connection.arrival,
+                rides: boarding.label.rides + 1,
+                walked: boarding.label.walked,
+                parent: Parent::Ride {
+                    boarded: boarding.connection,
+                    alighted: index,
+                },
+            };
+            let is_better =
+                |labels: &[Option<Label>], stop: usize, label: &Label| match labels[stop] {
+                    Some(ref other) => label.cost(options) < other.cost(options),
+                    None => true,
+                };
+            if !is_better(&labels, connection.to, &label) {
+                continue;
+            }
+            labels[connection.to] = Some(label);
+            consider(&mut finish, connection.to, &label);
+
+            for &(next, distance) in &self.stops[connection.to].footpaths {
+                if distance > options.max_walk_distance
+                    || label.walked + distance > options.max_total_walk
+                {
+                    continue;
+                }
+                let walk = Label {
+                    arrival: label.arrival + options.walk_time(distance),
+                    walked: label.walked + distance,
+                    parent: Parent::Walk {
+                        boarded: boarding.connection,
+                        alighted: index,
+                        distance,
+                    },
+                    ..label
+                };
+                if is_better(&labels, next, &walk) {
+                    labels[next] = Some(walk);
+                    consider(&mut finish, next, &walk);
+                }
+            }
+        }
+        (boardings, finish)
+    }
+
+    /// Follows `label` of `stop` back to the origin, returning the segments.
+    fn segments(
+        &self,
+        boardings: &[Option<Boarding>],
+        mut label: Label,
+        mut stop: usize,
+        from: Point,
+        departure: Timestamp,
+        options: &SearchOptions,
+    ) -> Vec<Segment<'_>> {
+        let mut segments = Vec::new();
+        loop {
+            match label.parent {
+                Parent::Access { distance } => {
+                    segments.push(Segment::Walk(WalkSegment {
+                        from: NamedPoint {
+                            id: None,
+                            name: None,
+                            loc: from,
+                        },
+                        to: self.stop_point(stop),
+                        start: departure,
+                        duration: options.walk_time(distance),
+                        distance,
+                    }));
+                    break;
+                }
+                Parent::Ride { boarded, alighted } => {
+                    let first = self.connections[boarded];
+                    let last = self.connections[alighted];
+                    let trip = &self.trips[first.trip];
+                    segments.push(Segment::Bus(BusSegment {
+                        bus: &trip.bus,
+                        trip: trip.id,
+                        typ: trip.typ,
+                        from: self.stop_point(first.from),
+                        to: self.stop_point(last.to),
+                        start: Timestamp::from_week_seconds(first.departure),
+                        duration: last.arrival - first.departure,
+                    }));
+                    stop = first.from;
+                    label = boardings[first.trip].expect("rode an unboarded trip").label;
+                }
+                Parent::Walk {
+                    boarded,
+                    alighted,
+                    distance,
+                } => {
+                    let from = self.connections[alighted].to;
+                    let duration = options.walk_time(distance);
+                    segments.push(Segment::Walk(WalkSegment {
+                        from: self.stop_point(from),
+                        to: self.stop_point(stop),
+                        start: Timestamp::from_week_seconds(label.arrival - duration),
+                        duration,
+                        distance,
+                    }));
+                    stop = from;
+                    // only the ride is needed to go on
+                    label.parent = Parent::Ride { boarded, alighted };
+                }
+            }
+        }
+        segments.reverse();
+        segments
+    }
+
+    /// Finds journeys from `from` to `to` departing between `earliest` and
+    /// `latest`, such that no other journey leaves later and arrives
+    /// earlier, like `Searcher::find_profile`. Transfer penalties are not
+    /// taken into account, and setting `max_transfers` or `max_total_walk`
+    /// to other than the default is an error.
+    pub fn find_profile(
+        &self,
+        from: Point,
+        to: Point,
+        earliest: Timestamp,
+        latest: Timestamp,
+        options: &SearchOptions,
+    ) -> Result<Vec<Route<'_>>, String> {
+        if options.max_transfers.is_some() {
+            return Err("max transfers are not supported in profiles".to_owned());
+        }
+        if options.max_total_walk != SearchOptions::default().max_total_walk {
+            return Err("max total walk is not supported in profiles".to_owned());
+        }
+        let start = earliest.week_seconds();
+        let end = start + earliest.seconds_until(latest);
+        let egress = self.egress(to, options);
+
+        // for each stop, pairs of latest time to be at the stop to board a
+        // vehicle and arrival at the destination, latest first
+        let mut profiles = vec![Vec::<(u64, u64)>::new(); self.stops.len()];
+        let mut trip_arrivals = vec![u64::MAX; self.trips.len()];
+        let lookup = |profile: &[(u64, u64)], time: u64| {
+            let count = profile.partition_point(|&(ready, _)| ready >= time);
+            if count == 0 {
+                u64::MAX
+            } else {
+                profile[count - 1].1
+            }
+        };
+
+        let first = self
+            .connections
+            .partition_point(|connection| connection.departure < start);
+        let last = self
+            .connections
+            .partition_point(|connection| connection.departure <= end + MAX_TRAVEL);
+        for connection in self.connections[first..last].iter().rev() {
+            let trip = &self.trips[connection.trip];
+            if !options.allows(&trip.bus, trip.typ) {
+                continue;
+            }
+            let mut arrival = trip_arrivals[connection.trip];
+            if let Some(distance) = egress[connection.to] {
+                arrival = arrival.min(connection.arrival + options.walk_time(distance));
+            }
+            arrival = arrival.min(lookup(&profiles[connection.to], connection.arrival));
+            for &(next, distance) in &self.stops[connection.to].footpaths {
+                if distance <= options.max_walk_distance {
+                    let walk_end = connection.arrival + options.walk_time(distance);
+                    arrival = arrival.min(lookup(&profiles[next], walk_end));
+                }
+            }
+            if arrival == u64::MAX {
+                continue;
+            }
+            trip_arrivals[connection.trip] = arrival;
+
+            let ready = connection.departure.saturating_sub(options.transfer_time);
+            let profile = &mut profiles[connection.from];
+            match profile.last().cloned() {
+                Some((_, best)) if best <= arrival => continue,
+                Some((last_ready, _)) if last_ready == ready => {
+                    profile.pop();
+                }
+                _ => {}
+            }
+            profile.push((ready, arrival));
+        }
+
+        // departures from the origin, keeping ones not dominated by a later
+        // departure
+        let mut journeys = Vec::new();
+        for (&stop, distance) in self.walking.within(from, options.max_walk_distance) {
+            let profile = &profiles[stop];
+            let walk_time = options.walk_time(distance);
+            for &(ready, arrival) in profile {
+                if ready >= start + walk_time && ready - walk_time <= end {
+                    journeys.push((ready - walk_time, arrival));
+                }
+            }
+        }
+        journeys.sort_by_key(|&(leave, arrival)| (leave, arrival));
+        let mut best_arrival = u64::MAX;
+        let mut useful = Vec::new();
+        for &(leave, arrival) in journeys.iter().rev() {
+            if arrival < best_arrival {
+                best_arrival = arrival;
+                useful.push(leave);
+            }
+        }
+        useful.reverse();
+
+        let exact = SearchOptions {
+            transfer_penalty: 0,
+            ..options.clone()
+        };
+        let routes = useful
+            .into_iter()
+            .filter_map(|leave| {
+                let departure = earliest.offset(leave - start);
+                self.find_route(from, to, departure, &exact)
+            })
+            .collect::<Vec<_>>();
+        debug!("Found {} journeys in profile", routes.len());
+        Ok(routes)
+    }
+}
+
+impl Engine for ConnectionScan {
+    fn find_route(
+        &self,
+        from: Point,
+        to: Point,
+        departure: Timestamp,
+        options: &SearchOptions,
+    ) -> Option<Route<'_>> {
+        let start = departure.week_seconds();
+        let egress = self.egress(to, options);
+        let (boardings, finish) = self.scan(from, start, &egress, options);
+        let finish = finish?;
+        debug!("Found route, arrived at {}", finish.arrival);
+
+        let mut segments = self.segments(
+            &boardings,
+            finish.label,
+            finish.stop,
+            from,
+            departure,
+            options,
+        );
+        let last_arrival = finish.label.arrival;
+        segments.push(Segment::Walk(WalkSegment {
+            from: self.stop_point(finish.stop),
+            to: NamedPoint {
+                id: None,
+                name: None,
+                loc: to,
+            },
+            start: Timestamp::from_week_seconds(last_arrival),
+            duration: finish.arrival - last_arrival,
+            distance: finish.distance,
+        }));
+        let mut route = Route {
+            segments,
+            departure_time: departure,
+            arrival_time: departure.offset(finish.arrival - start),
+        };
+        post_process_route(&mut route);
+        Some(route)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{assert_same_routes, ends, engine_network};
+    use super::super::Searcher;
+    use super::*;
+    use model::{Day, DayTime};
+
+    #[test]
+    fn same_routes_as_searcher() {
+        let (stops, schedules) = engine_network();
+        let searcher = Searcher::new(stops.clone(), schedules.clone());
+        let csa = ConnectionScan::new(stops, schedules);
+        assert_same_routes(&csa, &searcher);
+
+        let (from, to) = ends();
+        let options = SearchOptions::default();
+        let earliest = Timestamp::new(Day::Tuesday, DayTime::new(9, 0));
+        let latest = Timestamp::new(Day::Tuesday, DayTime::new(11, 0));
+        let expected = searcher.find_profile(from, to, earliest, latest, &options);
+        let routes = csa
+            .find_profile(from, to, earliest, latest, &options)
+            .unwrap();
+        let times = |routes: &[Route]| {
+            routes
+                .iter()
+                .map(|route| (route.departure_time, route.arrival_time))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(times(&routes), times(&expected));
+
+        let limited = SearchOptions {
+            max_transfers: Some(1),
+            ..options
+        };
+        assert!(csa
+            .find_profile(from, to, earliest, latest, &limited)
+            .is_err());
+    }
+}
@@ -0,0 +1,122 @@
+use model::Point;
+use std::collections::HashMap;
+
+/// Length of a degree of latitude, in meters.
+pub const METERS_PER_DEGREE: f64 = 111_320.0;
+
+/// Items bucketed by location into cells of roughly equal size.
+#[derive(Debug, Clone)]
+pub struct Grid<T> {
+    /// Cell size in meters.
+    cell: f64,
+    /// Cell size in degrees of latitude and longitude.
+    cell_lat: f64,
+    cell_lng: f64,
+    cells: HashMap<(i64, i64), Vec<(Point, T)>>,
+}
+
+impl<T> Grid<T> {
+    /// Builds a grid with cells of about `cell` by `cell` meters. Cells are
+    /// measured at the average latitude of the items, which is accurate
+    /// enough for a city.
+    pub fn new<I>(items: I, cell: f64) -> Grid<T>
+    where
+        I: IntoIterator<Item = (Point, T)>,
+    {
+        let items = items.into_iter().collect::<Vec<_>>();
+        let lat = if items.is_empty() {
+            0.0
+        } else {
+            items.iter().map(|&(point, _)| point.lat).sum::<f64>() / items.len() as f64
+        };
+        let cell_lat = cell / METERS_PER_DEGREE;
+        let cell_lng = cell_lat / lat.to_radians().cos().max(0.01);
+        let mut grid = Grid {
+            cell,
+            cell_lat,
+            cell_lng,
+            cells: HashMap::new(),
+        };
+        for (point, item) in items {
+            let key = grid.key(point);
+            grid.cells
+                .entry(key)
+                .or_insert_with(Vec::new)
+                .push((point, item));
+        }
+        grid
+    }
+
+    fn key(&self, point: Point) -> (i64, i64) {
+        (
+            (point.lat / self.cell_lat).floor() as i64,
+            (point.lng / self.cell_lng).floor() as i64,
+        )
+    }
+
+    /// Items within `radius` meters of `point`, with distances to them.
+    pub fn within(&self, point: Point, radius: f64) -> Vec<(&T, f64)> {
+        let mut found = Vec::new();
+        if radius.is_nan() || radius < 0.0 {
+            return found;
+        }
+        let (lat, lng) = self.key(point);
+        // one more cell on each side, since cells are only roughly square
+        let reach = (radius / self.cell).ceil() + 1.0;
+        let cells = if (2.0 * reach + 1.0).powi(2) > self.cells.len() as f64 {
+            // fewer cells are occupied than the square around the point has
+            self.cells.values().collect::<Vec<_>>()
+        } else {
+            let reach = reach as i64;
+            (lat - reach..=lat + reach)
+                .flat_map(|lat| {
+                    (lng - reach..=lng + reach).filter_map(move |lng| self.cells.get(&(lat, lng)))
+                })
+                .collect()
+        };
+        for cell in cells {
+            for &(other, ref item) in cell {
+                let distance = point.distance(other);
+                if distance <= radius {
+                    found.push((item, distance));
+                }
+            }
+        }
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_points_within_radius() {
+        let points = (0..100)
+            .map(|i| Point {
+                lat: 54.6 + f64::from(i / 10) * 0.002,
+                lng: 25.2 + f64::from(i % 10) * 0.003,
+            })
+            .collect::<Vec<_>>();
+        let grid = Grid::new(points.iter().cloned().zip(0..), 300.0);
+        let center = Point {
+            lat: 54.61,
+            lng: 25.21,
+        };
+        for &radius in &[50.0, 400.0, 1500.0] {
+            let mut found = grid
+                .within(center, radius)
+                .into_iter()
+                .map(|(&index, _)| index)
+                .collect::<Vec<_>>();
+            found.sort();
+            let expected = (0..points.len())
+                .filter(|&index| center.distance(points[index]) <= radius)
+                .collect::<Vec<_>>();
+            assert_eq!(found, expected);
+        }
+        assert_eq!(grid.within(center, 1e9).len(), points.len());
+        assert_eq!(grid.within(center, f64::INFINITY).len(), points.len());
+        assert!(grid.within(center, f64::NAN).is_empty());
+    }
+}
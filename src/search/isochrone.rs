@@ -0,0 +1,312 @@
+use super::grid::METERS_PER_DEGREE;
+use super::walking::Walking;
+use super::{SearchOptions, Searcher, StopRef};
+use model::{Point, Segment, Timestamp};
+use serde_json::Value;
+use std::collections::HashSet;
+
+// Size of the square cells making up the reachable area, in meters.
+const AREA_CELL: f64 = 100.0;
+
+/// A stop reached within the time budget.
+#[derive(Serialize, Debug, Clone)]
+pub struct ReachedStop<'a> {
+    #[serde(flatten)]
+    pub stop: StopRef<'a>,
+    pub arrival: Timestamp,
+    /// Seconds since the departure.
+    pub duration: u64,
+    pub transfers: u64,
+}
+
+/// Places reachable from `origin` at most `budget` seconds after
+/// `departure`.
+#[derive(Debug, Clone)]
+pub struct Isochrone<'a> {
+    pub origin: Point,
+    pub departure: Timestamp,
+    pub budget: u64,
+    /// Reached stops, earliest first.
+    pub stops: Vec<ReachedStop<'a>>,
+    area: Area,
+}
+
+/// Square cells of about `AREA_CELL` meters, as latitude and longitude
+/// indices.
+#[derive(Debug, Clone)]
+struct Area {
+    cell_lat: f64,
+    cell_lng: f64,
+    cells: HashSet<(i64, i64)>,
+}
+
+impl Area {
+    fn new(origin: Point) -> Area {
+        let cell_lat = AREA_CELL / METERS_PER_DEGREE;
+        Area {
+            cell_lat,
+            cell_lng: cell_lat / origin.lat.to_radians().cos().max(0.01),
+            cells: HashSet::new(),
+        }
+    }
+
+    /// Adds cells with centers within `radius` meters of `point`.
+    fn add(&mut self, point: Point, radius: f64) {
+        let reach = (radius / AREA_CELL).ceil() as i64 + 1;
+        let lat = (point.lat / self.cell_lat).floor() as i64;
+        let lng = (point.lng / self.cell_lng).floor() as i64;
+        for lat in lat - reach..=lat + reach {
+            for lng in lng - reach..=lng + reach {
+                let center = Point {
+                    lat: (lat as f64 + 0.5) * self.cell_lat,
+                    lng: (lng as f64 + 0.5) * self.cell_lng,
+                };
+                if point.distance(center) <= radius {
+                    self.cells.insert((lat, lng));
+                }
+            }
+        }
+    }
+
+    /// Adds the cell containing `point`.
+    fn add_cell(&mut self, point: Point) {
+        let lat = (point.lat / self.cell_lat).floor() as i64;
+        let lng = (point.lng / self.cell_lng).floor() as i64;
+        self.cells.insert((lat, lng));
+    }
+
+    /// Adds cells one can walk to within `radius` meters from `point`,
+    /// along streets where they are known and in a straight line otherwise.
+    fn add_walk<T>(&mut self, walking: &Walking<T>, point: Point, radius: f64) {
+        match walking.street_points(point, radius, AREA_CELL / 2.0) {
+            Some(points) => {
+                for point in points {
+                    self.add_cell(point);
+                }
+            }
+            None => self.add(point, radius),
+        }
+    }
+
+    /// Cells as GeoJSON polygon coordinates.
+    fn polygons(&self) -> Vec<Value> {
+        let mut cells = self.cells.iter().cloned().collect::<Vec<_>>();
+        cells.sort();
+        cells
+            .into_iter()
+            .map(|(lat, lng)| {
+                let south = lat as f64 * self.cell_lat;
+                let west = lng as f64 * self.cell_lng;
+                let north = south + self.cell_lat;
+                let east = west + self.cell_lng;
+                json!([[
+                    [west, south],
+                    [east, south],
+                    [east, north],
+                    [west, north],
+                    [west, south]
+                ]])
+            })
+            .collect()
+    }
+}
+
+impl<'a> Isochrone<'a> {
+    /// A GeoJSON feature collection with the area one can reach as a
+    /// multi-polygon, followed by reached stops as points.
+    pub fn geojson(&self) -> Value {
+        let mut features = vec![json!({
+            "type": "Feature",
+            "geometry": {
+                "type": "MultiPolygon",
+                "coordinates": self.area.polygons(),
+            },
+            "properties": {
+                "departure": self.departure,
+                "budget": self.budget,
+            },
+        })];
+        features.extend(self.stops.iter().map(|reached| {
+            json!({
+                "type": "Feature",
+                "geometry": {
+                    "type": "Point",
+                    "coordinates": [reached.stop.loc.lng, reached.stop.loc.lat],
+                },
+                "properties": {
+                    "id": reached.stop.id,
+                    "name": reached.stop.name,
+                    "arrival": reached.arrival,
+                    "duration": reached.duration,
+                    "transfers": reached.transfers,
+                },
+            })
+        }));
+        json!({
+            "type": "FeatureCollection",
+            "features": features,
+        })
+    }
+}
+
+impl Searcher {
+    /// Finds stops reachable from `from` at most `budget` seconds after
+    /// `departure`. The area also covers walks from the origin and from each
+    /// stop reached by a vehicle for the rest of the budget, along streets
+    /// where they are known.
+    pub fn find_isochrone(
+        &self,
+        from: Point,
+        departure: Timestamp,
+        budget: u64,
+        options: &SearchOptions,
+    ) -> Isochrone<'_> {
+        let times = self.explore(from, departure, Some(budget), options);
+        let mut area = Area::new(from);
+        let walk_radius = |seconds: u64, walked: f64| {
+            (seconds as f64 * options.walk_speed)
+                .min(options.max_walk_distance)
+                .min(options.max_total_walk - walked)
+        };
+        area.add_walk(&self.walking, from, walk_radius(budget, 0.0));
+
+        let mut stops = times
+            .into_iter()
+            .map(|(id, info)| {
+                let duration = departure.seconds_until(info.arrival);
+                let stop = self.stop_ref(id);
+                // walks to stops are already covered by the walk from the origin
+                if let Segment::Bus(_) = info.arriving_segment {
+                    area.add_walk(
+                        &self.walking,
+                        stop.loc,
+                        walk_radius(budget - duration, info.walked),
+                    );
+                }
+                ReachedStop {
+                    stop,
+                    arrival: info.arrival,
+                    duration,
+                    transfers: info.transfers,
+                }
+            })
+            .collect::<Vec<_>>();
+        stops.sort_by_key(|reached| (reached.duration, reached.stop.id));
+        debug!(
+            "Reached {} stops and {} cells",
+            stops.len(),
+            area.cells.len()
+        );
+        Isochrone {
+            origin: from,
+            departure,
+            budget,
+            stops,
+            area,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{schedule, stop, track};
+    use super::super::{FootpathOptions, Service};
+    use super::*;
+    use model::{Day, DayTime, Streets};
+
+    #[test]
+    fn stops_and_area_within_budget() {
+        let stops = vec![
+            stop("a", 54.70, 25.20),
+            stop("b", 54.72, 25.20),
+            stop("c", 54.74, 25.20),
+        ];
+        let schedules = vec![schedule(
+            "1",
+            vec![track("A", &["a", "b", "c"], &[DayTime::new(10, 0)], 600)],
+        )];
+        let searcher = Searcher::new(stops, schedules);
+        let from = Point {
+            lat: 54.70,
+            lng: 25.20,
+        };
+        let departure = Timestamp::new(Day::Tuesday, DayTime::new(9, 55));
+        let options = SearchOptions::default();
+        let isochrone = searcher.find_isochrone(from, departure, 20 * 60, &options);
+
+        let reached = isochrone
+            .stops
+            .iter()
+            .map(|reached| (reached.stop.id, reached.duration))
+            .collect::<Vec<_>>();
+        assert_eq!(reached, vec![("a", 0), ("b", 900)]);
+
+        let geojson = isochrone.geojson();
+        let features = geojson["features"].as_array().unwrap();
+        assert_eq!(features.len(), 3);
+        let cells = features[0]["geometry"]["coordinates"].as_array().unwrap();
+        // 5 minutes of walking at 4 km/h from b, 20 minutes capped at 500 m
+        // from the origin
+        let circles = ::std::f64::consts::PI * (333.0_f64.powi(2) + 500.0_f64.powi(2));
+        let expected = circles / (AREA_CELL * AREA_CELL);
+        assert!((cells.len() as f64 - expected).abs() < expected * 0.15);
+    }
+
+    #[test]
+    fn no_walks_from_stops_reached_on_foot() {
+        // about 440 m east of the origin
+        let searcher = Searcher::new(vec![stop("a", 54.70, 25.2068)], Vec::new());
+        let from = Point {
+            lat: 54.70,
+            lng: 25.20,
+        };
+        let departure = Timestamp::new(Day::Tuesday, DayTime::new(9, 55));
+        let options = SearchOptions::default();
+        let isochrone = searcher.find_isochrone(from, departure, 20 * 60, &options);
+
+        assert_eq!(isochrone.stops.len(), 1);
+        let area = &isochrone.area;
+        for &(lat, lng) in &area.cells {
+            let center = Point {
+                lat: (lat as f64 + 0.5) * area.cell_lat,
+                lng: (lng as f64 + 0.5) * area.cell_lng,
+            };
+            assert!(from.distance(center) <= 500.0 + AREA_CELL);
+        }
+    }
+
+    #[test]
+    fn area_follows_streets() {
+        let stops = vec![stop("a", 54.70, 25.20)];
+        // a single street heading east from the stop
+        let streets = Streets {
+            nodes: vec![
+                Point {
+                    lat: 54.70,
+                    lng: 25.200,
+                },
+                Point {
+                    lat: 54.70,
+                    lng: 25.220,
+                },
+            ],
+            edges: vec![(0, 1)],
+        };
+        let footpaths = FootpathOptions {
+            streets: Some(streets),
+            ..FootpathOptions::default()
+        };
+        let searcher = Searcher::build(stops, Vec::new(), Service::Weekly, &footpaths);
+        let from = Point {
+            lat: 54.70,
+            lng: 25.20,
+        };
+        let departure = Timestamp::new(Day::Tuesday, DayTime::new(9, 55));
+        let options = SearchOptions::default();
+        let isochrone = searcher.find_isochrone(from, departure, 20 * 60, &options);
+
+        // 500 m along the street rather than a circle of that radius
+        let cells = isochrone.area.cells.len();
+        assert!((5..=8).contains(&cells), "{} cells", cells);
+    }
+}
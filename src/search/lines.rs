@@ -0,0 +1,146 @@
+use super::{Line, Searcher, StopRef, StopTime};
+use model::Timestamp;
+
+/// A line with the stops each of its tracks serves.
+#[derive(Serialize, Debug, Clone)]
+pub struct LineTracks<'a> {
+    #[serde(flatten)]
+    pub line: &'a Line,
+    pub tracks: Vec<TrackStops<'a>>,
+}
+
+/// A track of a line and its stops, in order.
+#[derive(Serialize, Debug, Clone)]
+pub struct TrackStops<'a> {
+    pub name: &'a str,
+    pub stops: Vec<StopRef<'a>>,
+}
+
+/// A single run of a track.
+#[derive(Serialize, Debug, Clone)]
+pub struct Trip<'a> {
+    pub line: &'a str,
+    pub track: &'a str,
+    /// Every stop of the track, with times the trip reaches it.
+    pub stops: Vec<StopTime<'a>>,
+}
+
+impl Searcher {
+    /// Tracks of a line given by id or name, `None` if there is no such
+    /// line.
+    pub fn line_tracks(&self, line: &str) -> Option<LineTracks<'_>> {
+        let index = self.line_index(line)?;
+        let tracks = self.tracks[index]
+            .iter()
+            .map(|track| TrackStops {
+                name: &track.name,
+                stops: track.stops.iter().map(|id| self.stop_ref(id)).collect(),
+            })
+            .collect();
+        Some(LineTracks {
+            line: &self.lines[index],
+            tracks,
+        })
+    }
+
+    /// The first trip of a line's track leaving its first stop at or after
+    /// `departure`. Several tracks with the same name are treated as one.
+    /// `None` if there is no such line, track or trip.
+    pub fn find_trip(&self, line: &str, track: &str, departure: Timestamp) -> Option<Trip<'_>> {
+        let schedule = self.line_index(line)?;
+        let lower = track.to_lowercase();
+        let route = self.tracks[schedule]
+            .iter()
+            .enumerate()
+            .filter(|&(_, track)| track.name.to_lowercase() == lower)
+            .filter_map(|(index, track)| {
+                let first = self.stops.get(track.stops.first()?)?;
+                Some(first.routes.iter().filter(move |route| {
+                    route.trip.schedule == schedule && route.trip.track == index && route.index == 0
+                }))
+            })
+            .flatten()
+            .min_by_key(|route| departure.seconds_until(route.departure))?;
+        Some(Trip {
+            line: &self.lines[schedule].name,
+            track: &self.tracks[schedule][route.trip.track].name,
+            stops: self.trip_stops(route.trip, 0),
+        })
+    }
+
+    /// Index of a line by id, or by name if no line has that id.
+    fn line_index(&self, line: &str) -> Option<usize> {
+        let lower = line.to_lowercase();
+        self.lines
+            .iter()
+            .position(|known| known.id == line)
+            .or_else(|| {
+                self.lines
+                    .iter()
+                    .position(|known| known.name.to_lowercase() == lower)
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{schedule, stop, track};
+    use super::*;
+    use model::{Day, DayTime};
+
+    #[test]
+    fn tracks_and_trips_of_a_line() {
+        let stops = vec![
+            stop("a", 54.70, 25.20),
+            stop("b", 54.71, 25.20),
+            stop("c", 54.72, 25.20),
+        ];
+        let mut line = schedule(
+            "3G",
+            vec![
+                track(
+                    "A",
+                    &["a", "b", "c"],
+                    &[DayTime::new(8, 0), DayTime::new(9, 0)],
+                    300,
+                ),
+                track("B", &["c", "b"], &[DayTime::new(8, 30)], 300),
+            ],
+        );
+        line.id = "vln_3g".to_owned();
+        let searcher = Searcher::new(stops, vec![line]);
+
+        let tracks = searcher.line_tracks("3g").unwrap();
+        assert_eq!(tracks.line.id, "vln_3g");
+        let names = tracks
+            .tracks
+            .iter()
+            .map(|track| {
+                let stops = track.stops.iter().map(|stop| stop.id).collect::<Vec<_>>();
+                (track.name, stops)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec![("A", vec!["a", "b", "c"]), ("B", vec!["c", "b"])]
+        );
+
+        let departure = Timestamp::new(Day::Tuesday, DayTime::new(8, 1));
+        let trip = searcher.find_trip("vln_3g", "a", departure).unwrap();
+        let times = trip
+            .stops
+            .iter()
+            .map(|stop| (stop.stop.id, stop.time.to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            times,
+            vec![
+                ("a", "Tue 09:00".to_owned()),
+                ("b", "Tue 09:05".to_owned()),
+                ("c", "Tue 09:10".to_owned()),
+            ]
+        );
+        assert!(searcher.find_trip("3G", "C", departure).is_none());
+        assert!(searcher.line_tracks("4G").is_none());
+    }
+}
@@ -0,0 +1,120 @@
+use super::{SearchOptions, Searcher, StopInfo};
+use model::{Point, Timestamp};
+use std::collections::HashMap;
+
+impl Searcher {
+    /// Seconds from `departure` until arriving at each of `destinations`
+    /// from each of `origins`, `None` where there is no route. Every row
+    /// takes a single search from its origin, and arrivals are the same
+    /// `find_route` would find.
+    pub fn travel_times(
+        &self,
+        origins: &[Point],
+        destinations: &[Point],
+        departure: Timestamp,
+        options: &SearchOptions,
+    ) -> Vec<Vec<Option<u64>>> {
+        let egress = destinations
+            .iter()
+            .map(|&to| self.walks(to, options.max_walk_distance))
+            .collect::<Vec<_>>();
+        origins
+            .iter()
+            .map(|&from| {
+                let times = self.explore(from, departure, None, options);
+                egress
+                    .iter()
+                    .map(|walks| best_arrival(&times, walks, departure, options))
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+/// Seconds from `departure` until the best arrival at a destination, given
+/// labels of stops and walking distances from stops to the destination.
+fn best_arrival(
+    times: &HashMap<&str, StopInfo>,
+    walks: &HashMap<&str, f64>,
+    departure: Timestamp,
+    options: &SearchOptions,
+) -> Option<u64> {
+    walks
+        .iter()
+        .filter_map(|(stop, &distance)| {
+            let info = times.get(stop)?;
+            if info.walked + distance > options.max_total_walk {
+                return None;
+            }
+            let arrival = info.arrival.offset(options.walk_time(distance));
+            Some((arrival, info.penalty))
+        })
+        .min_by(|a, b| {
+            a.0.offset(a.1)
+                .compare_using_departure(b.0.offset(b.1), departure)
+        })
+        .map(|(arrival, _)| departure.seconds_until(arrival))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{schedule, stop, track};
+    use super::*;
+    use model::{Day, DayTime};
+
+    #[test]
+    fn matrix_matches_single_routes() {
+        let stops = vec![
+            stop("a", 54.70, 25.20),
+            stop("b", 54.71, 25.20),
+            stop("c", 54.72, 25.20),
+            stop("d", 54.80, 25.20),
+        ];
+        let schedules = vec![
+            schedule(
+                "1",
+                vec![track("A", &["a", "b"], &[DayTime::new(10, 0)], 300)],
+            ),
+            schedule(
+                "2",
+                vec![track("B", &["b", "c"], &[DayTime::new(10, 10)], 300)],
+            ),
+            schedule(
+                "3",
+                vec![track("C", &["c", "a"], &[DayTime::new(10, 30)], 900)],
+            ),
+        ];
+        let searcher = Searcher::new(stops, schedules);
+        let points = [
+            Point {
+                lat: 54.70,
+                lng: 25.20,
+            },
+            Point {
+                lat: 54.71,
+                lng: 25.20,
+            },
+            Point {
+                lat: 54.72,
+                lng: 25.20,
+            },
+            Point {
+                lat: 54.80,
+                lng: 25.20,
+            },
+        ];
+        let departure = Timestamp::new(Day::Tuesday, DayTime::new(9, 55));
+        let options = SearchOptions::default();
+        let matrix = searcher.travel_times(&points, &points, departure, &options);
+
+        for (from, row) in points.iter().zip(&matrix) {
+            for (to, &duration) in points.iter().zip(row) {
+                let route = searcher.find_route(*from, *to, departure, &options);
+                let expected = route.map(|route| departure.seconds_until(route.arrival_time));
+                assert_eq!(duration, expected);
+            }
+        }
+        assert_eq!(matrix[0][2], Some(20 * 60));
+        assert_eq!(matrix[0][3], None);
+    }
+}
@@ -1,42 +1,184 @@
 use itertools::Itertools;
 use model::{
-    BusSegment, Departure, NamedPoint, Point, Route, Schedule, Segment,
-    Stop as MStop, Timestamp, Track, TransportType, WalkSegment, DAYS,
+    BusSegment, Calendar, Date, Day, NamedPoint, Point, Route, Schedule, Segment, Stop as MStop,
+    Streets, Timestamp, Timetable, Track, Transfer, TransportType, TripId, WalkSegment, DAYS,
 };
 use std::cmp::Ordering;
 use std::collections::{BinaryHeap, HashMap};
 
-// Max walking distance, in meters.
-const MAX_WALK_DISTANCE: f64 = 500.0;
-const TRANSFER_DELAY: u64 = 3 * 60;
-const TRANSFER_PENALTY: u64 = 1 * 60;
+const DAY: u64 = 24 * 3600;
+const WEEK: u64 = 7 * DAY;
+// Size of spatial index cells, in meters.
+const GRID_CELL: f64 = 500.0;
+
+mod alternatives;
+mod board;
+mod csa;
+mod grid;
+mod isochrone;
+mod lines;
+mod matrix;
+mod names;
+mod options;
+mod pareto;
+mod profile;
+mod raptor;
+mod reverse;
+mod walking;
+
+pub use self::board::{StopDeparture, StopTime};
+pub use self::csa::ConnectionScan;
+pub use self::isochrone::{Isochrone, ReachedStop};
+pub use self::lines::{LineTracks, TrackStops, Trip};
+use self::names::StopNames;
+pub use self::names::{Locator, Platform, StopMatch};
+pub use self::options::SearchOptions;
+pub use self::raptor::Raptor;
+use self::walking::Walking;
 
 #[derive(Debug, Clone)]
 struct Stop {
     name: String,
+    area: Option<String>,
+    direction: Option<String>,
     loc: Point,
     routes: Vec<StopRoute>,
+    /// Routes arriving to this stop, as previous stop id and index in its
+    /// `routes`.
+    incoming: Vec<(String, usize)>,
+    /// Stops one can walk to from here, with walking distances.
+    footpaths: Vec<(String, f64)>,
+    /// Stops one can walk here from, with walking distances.
+    incoming_footpaths: Vec<(String, f64)>,
 }
 
 #[derive(Debug, Clone)]
 struct StopRoute {
     bus: String,
+    trip: TripId,
     typ: TransportType,
+    /// Position of the stop in its track.
+    index: usize,
     next_stop: String,
     departure: Timestamp,
     arrival: Timestamp,
     duration: u64,
 }
 
+/// Decides which timetables populate each day of the searched week.
+#[derive(Copy, Clone)]
+pub enum Service<'a> {
+    /// Every week is the same, timetables run by their days of week.
+    Weekly,
+    /// The week starting at given date, with holidays and exceptions taken
+    /// from the calendar.
+    Dated {
+        calendar: &'a Calendar,
+        week_start: Date,
+    },
+}
+
+impl<'a> Service<'a> {
+    fn runs(&self, timetable: &Timetable, day: Day) -> bool {
+        match *self {
+            Service::Weekly => timetable.works_on_day(day),
+            Service::Dated {
+                calendar,
+                week_start,
+            } => {
+                // the difference is the same whichever day the week starts on
+                let offset = (i64::from(day.index()) - i64::from(week_start.weekday().index()))
+                    .rem_euclid(7);
+                timetable.works_on_date(week_start.offset(offset), calendar)
+            }
+        }
+    }
+}
+
+/// How walking distances are found when building a searcher.
+#[derive(Debug, Clone)]
+pub struct FootpathOptions {
+    /// Stops at most this many meters walk apart are connected. Walks
+    /// between stops are never longer, whatever the query options.
+    pub radius: f64,
+    /// Connections replacing the ones derived from distance.
+    pub transfers: Vec<Transfer>,
+    /// Streets to walk along, `None` to walk in straight lines.
+    pub streets: Option<Streets>,
+}
+
+impl Default for FootpathOptions {
+    fn default() -> FootpathOptions {
+        FootpathOptions {
+            radius: 1000.0,
+            transfers: Vec::new(),
+            streets: None,
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct Searcher {
     stops: HashMap<String, Stop>,
+    lines: Vec<Line>,
+    /// Tracks of each line, indexed by `TripId::schedule` and `TripId::track`.
+    tracks: Vec<Vec<Track>>,
+    /// Ids of stops, by walking distance.
+    walking: Walking<String>,
+    names: StopNames,
+}
+
+/// A stop known to the searcher.
+#[derive(Serialize, Debug, Copy, Clone)]
+pub struct StopRef<'a> {
+    pub id: &'a str,
+    pub name: &'a str,
+    #[serde(flatten)]
+    pub loc: Point,
+}
+
+/// A line the searcher was built from, indexed by `TripId::schedule`.
+#[derive(Serialize, Debug, Clone)]
+pub struct Line {
+    pub id: String,
+    pub name: String,
+    pub long_name: String,
+    #[serde(rename = "transport")]
+    pub typ: TransportType,
+}
+
+/// A route planning algorithm. Engines built from the same network should
+/// find equally good routes, so they can be swapped and compared.
+pub trait Engine {
+    /// Finds the route from `from` to `to` that arrives earliest, counting
+    /// each boarding as `options.transfer_penalty` seconds of travel.
+    fn find_route(
+        &self,
+        from: Point,
+        to: Point,
+        departure: Timestamp,
+        options: &SearchOptions,
+    ) -> Option<Route<'_>>;
+}
+
+impl Engine for Searcher {
+    fn find_route(
+        &self,
+        from: Point,
+        to: Point,
+        departure: Timestamp,
+        options: &SearchOptions,
+    ) -> Option<Route<'_>> {
+        Searcher::find_route(self, from, to, departure, options)
+    }
 }
 
 struct StopInfo<'a> {
-    walk_finish: Option<Timestamp>,
     arrival: Timestamp,
     transfers: u64,
+    walked: f64,
+    /// Seconds of penalties for boarding vehicles.
+    penalty: u64,
     arriving_segment: Segment<'a>,
     parent: Option<&'a str>,
 }
@@ -45,30 +187,22 @@ struct StopInfo<'a> {
 struct HeapItem<'a> {
     departure: Timestamp,
     arrival: Timestamp,
+    /// Arrival delayed by the boarding penalties.
+    cost: Timestamp,
     transfers: u64,
+    penalty: u64,
+    walked: f64,
     stop: &'a str,
     parent: Option<&'a str>,
     segment: Segment<'a>,
 }
 
-fn compare_points(
-    departure: Timestamp,
-    first: (Timestamp, u64),
-    second: (Timestamp, u64),
-) -> Ordering {
-    let first = first.0.offset(TRANSFER_PENALTY * first.1);
-    let second = second.0.offset(TRANSFER_PENALTY * second.1);
-    first.compare_using_departure(second, departure)
-}
-
 impl<'a> Ord for HeapItem<'a> {
     fn cmp(&self, other: &HeapItem<'a>) -> Ordering {
         // we want earliest (smallest) items to come first, so they must be greatest
-        compare_points(
-            self.departure,
-            (self.arrival, self.transfers),
-            (other.arrival, other.transfers),
-        ).reverse()
+        self.cost
+            .compare_using_departure(other.cost, self.departure)
+            .reverse()
     }
 }
 
@@ -88,76 +222,189 @@ impl<'a> Eq for HeapItem<'a> {}
 
 impl Searcher {
     pub fn new(stops: Vec<MStop>, schedules: Vec<Schedule>) -> Searcher {
+        Searcher::build(
+            stops,
+            schedules,
+            Service::Weekly,
+            &FootpathOptions::default(),
+        )
+    }
+
+    /// Builds a searcher for the seven days starting at `week_start`, using
+    /// the calendar to decide which timetables run on each date. Use
+    /// `Timestamp::on_date` to query routes for dates within that week.
+    pub fn with_calendar(
+        stops: Vec<MStop>,
+        schedules: Vec<Schedule>,
+        calendar: &Calendar,
+        week_start: Date,
+    ) -> Searcher {
+        Searcher::build(
+            stops,
+            schedules,
+            Service::Dated {
+                calendar,
+                week_start,
+            },
+            &FootpathOptions::default(),
+        )
+    }
+
+    /// Builds a searcher running timetables by `service`, with walking
+    /// distances found using `footpaths`.
+    pub fn build(
+        stops: Vec<MStop>,
+        schedules: Vec<Schedule>,
+        service: Service,
+        footpaths: &FootpathOptions,
+    ) -> Searcher {
+        let names = StopNames::new(&stops);
         let stops = stops
             .into_iter()
             .map(|stop| {
-                let MStop { id, name, loc } = stop;
+                let MStop {
+                    id,
+                    name,
+                    area,
+                    direction,
+                    loc,
+                } = stop;
                 (
                     id,
                     Stop {
                         name,
+                        area,
+                        direction,
                         loc,
                         routes: Vec::new(),
+                        incoming: Vec::new(),
+                        footpaths: Vec::new(),
+                        incoming_footpaths: Vec::new(),
                     },
                 )
             })
             .collect::<HashMap<_, _>>();
-        let mut searcher = Searcher { stops };
-        for schedule in schedules {
-            searcher.add_schedule(schedule);
+        let mut searcher = Searcher {
+            stops,
+            lines: Vec::new(),
+            tracks: Vec::new(),
+            walking: Walking::new(Vec::new(), None),
+            names,
+        };
+        for (index, schedule) in schedules.into_iter().enumerate() {
+            searcher.add_schedule(index, schedule, service);
         }
         searcher.fix_stops();
+        searcher.set_footpaths(footpaths);
         searcher
     }
 
-    fn add_schedule(&mut self, schedule: Schedule) {
-        for track in schedule.tracks {
-            self.add_track(schedule.name.clone(), schedule.transport_type, track);
+    /// Replaces walking distances to and between stops with ones found
+    /// using `options`.
+    pub fn with_footpaths(mut self, options: &FootpathOptions) -> Searcher {
+        self.set_footpaths(options);
+        self
+    }
+
+    fn set_footpaths(&mut self, options: &FootpathOptions) {
+        let stops = self
+            .stops
+            .iter()
+            .map(|(id, stop)| (stop.loc, id.clone()))
+            .collect();
+        self.walking = Walking::new(stops, options.streets.as_ref());
+        let ids = self.walking.items().cloned().collect::<Vec<_>>();
+        let paths = {
+            let ids = ids.iter().map(String::as_str).collect::<Vec<_>>();
+            footpaths(&self.walking, &ids, options)
+        };
+        for stop in self.stops.values_mut() {
+            stop.footpaths.clear();
+            stop.incoming_footpaths.clear();
         }
+        let mut total = 0;
+        for (from, paths) in ids.iter().zip(paths) {
+            for (to, distance) in paths {
+                let to = &ids[to];
+                self.stops
+                    .get_mut(from)
+                    .unwrap()
+                    .footpaths
+                    .push((to.clone(), distance));
+                self.stops
+                    .get_mut(to)
+                    .unwrap()
+                    .incoming_footpaths
+                    .push((from.clone(), distance));
+                total += 1;
+            }
+        }
+        debug!("Built {} footpaths", total);
     }
 
-    fn add_track(&mut self, name: String, typ: TransportType, track: Track) {
+    fn add_schedule(&mut self, index: usize, schedule: Schedule, service: Service) {
+        self.lines.push(Line {
+            id: schedule.id.clone(),
+            name: schedule.name.clone(),
+            long_name: schedule.long_name.clone(),
+            typ: schedule.transport_type,
+        });
+        for (track_index, track) in schedule.tracks.iter().enumerate() {
+            self.add_track(
+                (index, track_index),
+                schedule.name.clone(),
+                schedule.transport_type,
+                track,
+                service,
+            );
+        }
+        self.tracks.push(schedule.tracks);
+    }
+
+    fn add_track(
+        &mut self,
+        (schedule, track_index): (usize, usize),
+        name: String,
+        typ: TransportType,
+        track: &Track,
+        service: Service,
+    ) {
         for ((ai, a), (bi, b)) in track.stops.iter().enumerate().tuple_windows() {
-            let stop = self.stops
+            let stop = self
+                .stops
                 .get_mut(a)
                 .expect("schedule refers to non-existing stop");
 
             for &day in DAYS {
-                for tt in &track.timetables {
-                    if !tt.works_on_day(day) {
+                for (tt_index, tt) in track.timetables.iter().enumerate() {
+                    if !service.runs(tt, day) {
                         continue;
                     }
-                    for dep in &tt.departures {
-                        match *dep {
-                            Departure::Exact(time) => {
-                                let stop_time = tt.find_stop_time(ai, time);
-                                let next_stop_time = tt.find_stop_time(bi, time);
-                                let ride_time = next_stop_time
-                                    .raw
-                                    .checked_sub(stop_time.raw)
-                                    .expect("time subtract underflow");
-
-                                let route = StopRoute {
-                                    bus: name.clone(),
-                                    typ,
-                                    next_stop: b.clone(),
-                                    departure: Timestamp {
-                                        day,
-                                        time: stop_time,
-                                    },
-                                    arrival: Timestamp {
-                                        day,
-                                        time: next_stop_time,
-                                    },
-                                    duration: ride_time,
-                                };
-                                stop.routes.push(route);
-                            }
-                            Departure::Periodic(_) => {
-                                // a wild hack appeared!
-                                // ignore periodic departures
-                            }
-                        }
+                    for (dep_index, time) in tt.departure_times().enumerate() {
+                        let stop_time = tt.find_stop_time(ai, time);
+                        let next_stop_time = tt.find_stop_time(bi, time);
+                        let ride_time = next_stop_time
+                            .raw
+                            .checked_sub(stop_time.raw)
+                            .expect("time subtract underflow");
+
+                        let route = StopRoute {
+                            bus: name.clone(),
+                            trip: TripId {
+                                schedule,
+                                track: track_index,
+                                timetable: tt_index,
+                                departure: dep_index,
+                                day,
+                            },
+                            typ,
+                            index: ai,
+                            next_stop: b.clone(),
+                            departure: Timestamp::new(day, stop_time),
+                            arrival: Timestamp::new(day, next_stop_time),
+                            duration: ride_time,
+                        };
+                        stop.routes.push(route);
                     }
                 }
             }
@@ -170,6 +417,17 @@ impl Searcher {
             stop.routes.sort_by_key(|route| route.departure);
             total_edges += stop.routes.len();
         }
+        let mut incoming = Vec::with_capacity(total_edges);
+        for (id, stop) in &self.stops {
+            for (index, route) in stop.routes.iter().enumerate() {
+                incoming.push((route.next_stop.clone(), id.clone(), index));
+            }
+        }
+        for (stop, prev_stop, index) in incoming {
+            if let Some(stop) = self.stops.get_mut(&stop) {
+                stop.incoming.push((prev_stop, index));
+            }
+        }
         debug!(
             "Built graph: nodes {}, edges: {}",
             self.stops.len(),
@@ -177,34 +435,137 @@ impl Searcher {
         );
     }
 
-    pub fn find_route(&self, from: Point, to: Point, departure: Timestamp) -> Option<Route> {
+    pub fn stops(&self) -> impl Iterator<Item = StopRef<'_>> {
+        self.stops.iter().map(|(id, stop)| StopRef {
+            id,
+            name: &stop.name,
+            loc: stop.loc,
+        })
+    }
+
+    pub fn lines(&self) -> &[Line] {
+        &self.lines
+    }
+
+    /// Stops within `radius` meters walk of `point` with walking distances
+    /// to them, nearest first.
+    pub fn stops_near(&self, point: Point, radius: f64) -> Vec<(StopRef<'_>, f64)> {
+        let mut stops = self
+            .walking
+            .within(point, radius)
+            .into_iter()
+            .map(|(id, distance)| (self.stop_ref(id), distance))
+            .collect::<Vec<_>>();
+        stops.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+        stops
+    }
+
+    pub fn find_route(
+        &self,
+        from: Point,
+        to: Point,
+        departure: Timestamp,
+        options: &SearchOptions,
+    ) -> Option<Route<'_>> {
+        let egress = self.walks(to, options.max_walk_distance);
+        let mut times = self.explore(from, departure, None, options);
+
+        let (final_stop, arrival_time, _) = times
+            .iter()
+            .filter_map(|(&stop, info)| {
+                let distance = *egress.get(stop)?;
+                if info.walked + distance > options.max_total_walk {
+                    return None;
+                }
+                let arrival = info.arrival.offset(options.walk_time(distance));
+                Some((stop, arrival, info.penalty))
+            })
+            .min_by(|a, b| {
+                a.1.offset(a.2)
+                    .compare_using_departure(b.1.offset(b.2), departure)
+            })?;
+
+        debug!("Found route, arrived at {}", arrival_time);
+
+        let mut route_segments = Vec::new();
+        // Segment of walking from the last stop to the end point.
+        let distance = egress[final_stop];
+        route_segments.push(Segment::Walk(WalkSegment {
+            from: self.stop_point(final_stop),
+            to: NamedPoint {
+                id: None,
+                name: None,
+                loc: to,
+            },
+            start: times[final_stop].arrival,
+            duration: options.walk_time(distance),
+            distance,
+        }));
+
+        let mut current = final_stop;
+        let departure_time;
+
+        loop {
+            let info = times.remove(current).unwrap();
+            route_segments.push(info.arriving_segment);
+            match info.parent {
+                Some(parent) => current = parent,
+                None => {
+                    // segment of walking from the start point to first stop
+                    departure_time = info.arriving_segment.start();
+                    break;
+                }
+            }
+        }
+
+        route_segments.reverse();
+
+        let mut route = Route {
+            segments: route_segments,
+            departure_time,
+            arrival_time,
+        };
+
+        post_process_route(&mut route);
+
+        Some(route)
+    }
+
+    /// Finds the best way to reach every stop from `from`, skipping stops
+    /// reached more than `budget` seconds after the departure.
+    fn explore(
+        &self,
+        from: Point,
+        departure: Timestamp,
+        budget: Option<u64>,
+        options: &SearchOptions,
+    ) -> HashMap<&str, StopInfo<'_>> {
         let mut times = HashMap::<&str, StopInfo>::new();
         let mut queue = BinaryHeap::new();
 
-        for (name, stop) in &self.stops {
-            let distance = from.distance(stop.loc);
-            if distance > MAX_WALK_DISTANCE {
-                continue;
-            }
-            let walk_time = walk_time(distance);
+        let radius = options.max_walk_distance.min(options.max_total_walk);
+        for (name, distance) in self.walking.within(from, radius) {
+            let walk_time = options.walk_time(distance);
             let arrival = departure.offset(walk_time);
             let heap_item = HeapItem {
                 departure,
                 arrival,
+                cost: arrival,
                 transfers: 0,
+                penalty: 0,
+                walked: distance,
                 stop: name,
                 parent: None,
                 segment: Segment::Walk(WalkSegment {
                     from: NamedPoint {
-                        loc: from,
+                        id: None,
                         name: None,
+                        loc: from,
                     },
-                    to: NamedPoint {
-                        loc: stop.loc,
-                        name: Some(&stop.name),
-                    },
-                    start: departure.time,
+                    to: self.stop_point(name),
+                    start: departure,
                     duration: walk_time,
+                    distance,
                 }),
             };
             queue.push(heap_item);
@@ -214,6 +575,13 @@ impl Searcher {
             if times.contains_key(item.stop) {
                 continue;
             }
+            let is_late = match budget {
+                Some(budget) => departure.seconds_until(item.arrival) > budget,
+                None => false,
+            };
+            if is_late {
+                continue;
+            }
             let reached_stop_at = item.arrival;
             trace!(
                 "Reached stop {} ({}) at {} ({} transfers)",
@@ -223,51 +591,59 @@ impl Searcher {
                 item.transfers,
             );
             let stop = &self.stops[item.stop];
-            let dist_to_end = stop.loc.distance(to);
-            let walk_finish = if dist_to_end > MAX_WALK_DISTANCE {
-                None
-            } else {
-                Some(reached_stop_at.offset(walk_time(dist_to_end)))
-            };
             times.insert(
                 item.stop,
                 StopInfo {
                     arrival: reached_stop_at,
                     transfers: item.transfers,
+                    walked: item.walked,
+                    penalty: item.penalty,
                     arriving_segment: item.segment,
                     parent: item.parent,
-                    walk_finish,
                 },
             );
 
             // check outgoing bus routes
             for route in &stop.routes {
+                if !options.allows(&route.bus, route.typ) {
+                    continue;
+                }
                 let is_transfering = match item.segment {
                     Segment::Walk(_) => true,
-                    Segment::Bus(segment) => {
-                        segment.bus != &route.bus || reached_stop_at != route.departure
-                    }
+                    Segment::Bus(segment) => segment.trip != route.trip,
                 };
+                if is_transfering && !options.may_board(item.transfers) {
+                    continue;
+                }
                 let transfer_time = if is_transfering {
-                    reached_stop_at.offset(TRANSFER_DELAY)
+                    reached_stop_at.offset(options.transfer_time)
                 } else {
                     reached_stop_at
                 };
                 let transfers = item.transfers + is_transfering as u64;
+                let penalty = if is_transfering {
+                    item.penalty + options.boarding_penalty(&route.bus)
+                } else {
+                    item.penalty
+                };
                 if transfer_time.is_followed_by(route.departure) {
                     // we can use this route
                     let segment = Segment::Bus(BusSegment {
                         bus: &route.bus,
+                        trip: route.trip,
                         typ: route.typ,
-                        from_stop: &item.stop,
-                        to_stop: &route.next_stop,
-                        start: route.departure.time,
+                        from: self.stop_point(item.stop),
+                        to: self.stop_point(&route.next_stop),
+                        start: route.departure,
                         duration: route.duration,
                     });
                     let item = HeapItem {
                         departure,
                         arrival: route.arrival,
+                        cost: route.arrival.offset(penalty),
                         transfers,
+                        penalty,
+                        walked: item.walked,
                         stop: &route.next_stop,
                         parent: Some(item.stop),
                         segment,
@@ -278,29 +654,28 @@ impl Searcher {
 
             // try to walk to nearby stops, but only if we haven't walked already
             if let Segment::Bus(_) = item.segment {
-                for (id, next_stop) in &self.stops {
-                    let distance = stop.loc.distance(next_stop.loc);
-                    if distance > MAX_WALK_DISTANCE {
+                for &(ref id, distance) in &stop.footpaths {
+                    if distance > options.max_walk_distance
+                        || item.walked + distance > options.max_total_walk
+                    {
                         continue;
                     }
-                    let walk_time = walk_time(distance);
+                    let walk_time = options.walk_time(distance);
                     let next_stop_arrival = reached_stop_at.offset(walk_time);
                     let segment = Segment::Walk(WalkSegment {
-                        from: NamedPoint {
-                            loc: stop.loc,
-                            name: Some(&stop.name),
-                        },
-                        to: NamedPoint {
-                            loc: next_stop.loc,
-                            name: Some(&next_stop.name),
-                        },
-                        start: reached_stop_at.time,
+                        from: self.stop_point(item.stop),
+                        to: self.stop_point(id),
+                        start: reached_stop_at,
                         duration: walk_time,
+                        distance,
                     });
                     let item = HeapItem {
                         departure,
                         arrival: next_stop_arrival,
+                        cost: next_stop_arrival.offset(item.penalty),
                         transfers: item.transfers,
+                        penalty: item.penalty,
+                        walked: item.walked + distance,
                         stop: id,
                         parent: Some(item.stop),
                         segment,
@@ -310,102 +685,518 @@ impl Searcher {
             }
         }
 
-        let (&final_stop, arrival_time, _) = times
+        times
+    }
+
+    /// Walking distances between `point` and stops within `radius` meters
+    /// walk of it.
+    fn walks(&self, point: Point, radius: f64) -> HashMap<&str, f64> {
+        self.walking
+            .within(point, radius)
+            .into_iter()
+            .map(|(id, distance)| (id.as_str(), distance))
+            .collect()
+    }
+
+    fn stop_ref<'a>(&'a self, id: &'a str) -> StopRef<'a> {
+        let stop = &self.stops[id];
+        StopRef {
+            id,
+            name: &stop.name,
+            loc: stop.loc,
+        }
+    }
+
+    /// Stops of `trip` from position `from` in its track on, with times the
+    /// trip reaches them.
+    fn trip_stops(&self, trip: TripId, from: usize) -> Vec<StopTime<'_>> {
+        let track = &self.tracks[trip.schedule][trip.track];
+        let timetable = &track.timetables[trip.timetable];
+        let first = timetable
+            .departure_times()
+            .nth(trip.departure)
+            .expect("trip should depart from the first stop");
+        track
+            .stops
             .iter()
-            .flat_map(|(stop, info)| Some((stop, info.walk_finish?, info.transfers)))
-            .min_by(|a, b| compare_points(departure, (a.1, a.2), (b.1, b.2)))?;
+            .enumerate()
+            .skip(from)
+            .map(|(index, id)| StopTime {
+                stop: self.stop_ref(id),
+                time: Timestamp::new(trip.day, timetable.find_stop_time(index, first)),
+            })
+            .collect()
+    }
 
-        debug!("Found route, arrived at {}", arrival_time);
+    fn stop_point<'a>(&'a self, id: &'a str) -> NamedPoint<'a> {
+        let stop = &self.stops[id];
+        NamedPoint {
+            id: Some(id),
+            name: Some(&stop.name),
+            loc: stop.loc,
+        }
+    }
+}
 
-        let mut route_segments = Vec::new();
-        // Segment of walking from the last stop to the end point.
-        route_segments.push(Segment::Walk(WalkSegment {
-            from: NamedPoint {
-                loc: self.stops[final_stop].loc,
-                name: Some(&self.stops[final_stop].name),
+/// For each stop in `walking`, indices of the stops one can walk to and
+/// walking distances to them. `ids` are ids of the stops, in the same order.
+fn footpaths<T>(
+    walking: &Walking<T>,
+    ids: &[&str],
+    options: &FootpathOptions,
+) -> Vec<Vec<(usize, f64)>> {
+    let mut paths = walking.paths(options.radius);
+    let indices = ids
+        .iter()
+        .enumerate()
+        .map(|(index, &id)| (id, index))
+        .collect::<HashMap<_, _>>();
+    for transfer in &options.transfers {
+        let from = indices.get(transfer.from.as_str());
+        let to = indices.get(transfer.to.as_str());
+        let (from, to) = match (from, to) {
+            (Some(&from), Some(&to)) => (from, to),
+            _ => {
+                warn!(
+                    "Transfer from {} to {} refers to unknown stop",
+                    transfer.from, transfer.to
+                );
+                continue;
+            }
+        };
+        paths[from].retain(|&(other, _)| other != to);
+        if let Some(distance) = transfer.distance {
+            paths[from].push((to, distance));
+        }
+    }
+    for paths in &mut paths {
+        paths.sort_by_key(|&(other, _)| other);
+    }
+    paths
+}
+
+/// Joins adjacent segments that continue each other.
+fn post_process_route(route: &mut Route) {
+    // join adjacent bus segments that use the same vehicle
+    route.segments.dedup_by(|b, a| match (a, b) {
+        (&mut Segment::Bus(ref mut a), &mut Segment::Bus(ref mut b)) => {
+            if a.trip != b.trip {
+                return false;
+            }
+            a.duration += b.duration;
+            a.to = b.to;
+            true
+        }
+        _ => false,
+    });
+    // join adjacent walking segments
+    route.segments.dedup_by(|b, a| match (a, b) {
+        (&mut Segment::Walk(ref mut a), &mut Segment::Walk(ref mut b)) => {
+            a.duration += b.duration;
+            a.distance += b.distance;
+            a.to = b.to;
+            true
+        }
+        _ => false,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use model::{DayTime, Departure, Durations, Entry};
+
+    pub fn stop(id: &str, lat: f64, lng: f64) -> MStop {
+        named_stop(id, id, lat, lng)
+    }
+
+    pub fn named_stop(id: &str, name: &str, lat: f64, lng: f64) -> MStop {
+        MStop {
+            id: id.to_owned(),
+            name: name.to_owned(),
+            area: None,
+            direction: None,
+            loc: Point { lat, lng },
+        }
+    }
+
+    /// A track running every day, taking `ride` seconds between stops.
+    pub fn track(name: &str, stops: &[&str], departures: &[DayTime], ride: u64) -> Track {
+        let durations = (0..stops.len())
+            .map(|i| Durations {
+                entries: vec![Entry {
+                    from: DayTime::new(0, 0),
+                    to: DayTime { raw: 24 * 3600 },
+                    time: i as u64 * ride,
+                }],
+            })
+            .collect();
+        Track {
+            name: name.to_owned(),
+            stops: stops.iter().map(|&s| s.to_owned()).collect(),
+            timetables: vec![Timetable {
+                days: 0b1111111,
+                departures: departures.iter().map(|&t| Departure::Exact(t)).collect(),
+                durations,
+                added_dates: Vec::new(),
+                removed_dates: Vec::new(),
+                valid_dates: None,
+            }],
+        }
+    }
+
+    pub fn schedule(name: &str, tracks: Vec<Track>) -> Schedule {
+        Schedule {
+            id: name.to_owned(),
+            name: name.to_owned(),
+            long_name: name.to_owned(),
+            tracks,
+            transport_type: TransportType::Bus,
+        }
+    }
+
+    /// Stops a, b and c in a line, with line 1 from a to b, line 2 from b to
+    /// c after it and line 3 from a to c taking `direct` seconds.
+    pub fn transfer_network(direct: u64) -> (Vec<MStop>, Vec<Schedule>) {
+        let stops = vec![
+            stop("a", 54.70, 25.20),
+            stop("b", 54.71, 25.20),
+            stop("c", 54.72, 25.20),
+        ];
+        let schedules = vec![
+            schedule(
+                "1",
+                vec![track("A", &["a", "b"], &[DayTime::new(10, 0)], 300)],
+            ),
+            schedule(
+                "2",
+                vec![track("B", &["b", "c"], &[DayTime::new(10, 10)], 300)],
+            ),
+            schedule(
+                "3",
+                vec![track("C", &["a", "c"], &[DayTime::new(10, 5)], direct)],
+            ),
+        ];
+        (stops, schedules)
+    }
+
+    /// Points at stops a and c of `transfer_network` and `engine_network`.
+    pub fn ends() -> (Point, Point) {
+        (
+            Point {
+                lat: 54.70,
+                lng: 25.20,
             },
-            to: NamedPoint {
-                loc: to,
-                name: None,
+            Point {
+                lat: 54.72,
+                lng: 25.20,
             },
-            start: times[final_stop].arrival.time,
-            duration: walk_time(self.stops[final_stop].loc.distance(to)),
-        }));
+        )
+    }
 
-        let mut current = final_stop;
-        let departure_time;
+    /// A network with a transfer between nearby stops and trips past
+    /// midnight, for comparing engines with `Searcher`.
+    pub fn engine_network() -> (Vec<MStop>, Vec<Schedule>) {
+        let stops = vec![
+            stop("a", 54.70, 25.20),
+            stop("b", 54.71, 25.20),
+            stop("b2", 54.7105, 25.2005),
+            stop("c", 54.72, 25.20),
+        ];
+        let schedules = vec![
+            schedule(
+                "1",
+                vec![track(
+                    "A",
+                    &["a", "b"],
+                    &[DayTime::new(10, 0), DayTime::new(23, 50)],
+                    300,
+                )],
+            ),
+            schedule(
+                "2",
+                vec![track(
+                    "B",
+                    &["b2", "c"],
+                    &[DayTime::new(10, 10), DayTime::new(0, 30)],
+                    300,
+                )],
+            ),
+            schedule(
+                "3",
+                vec![track("C", &["a", "c"], &[DayTime::new(10, 5)], 1800)],
+            ),
+        ];
+        (stops, schedules)
+    }
 
-        loop {
-            let info = times.remove(current).unwrap();
-            route_segments.push(info.arriving_segment);
-            match info.parent {
-                Some(parent) => current = parent,
-                None => {
-                    // segment of walking from the start point to first stop
-                    let stop_pos = self.stops[current].loc;
-                    let walk_time = walk_time(from.distance(stop_pos));
-                    departure_time = info.arrival.neg_offset(walk_time).time;
-                    break;
-                }
-            }
+    /// Checks that `engine`, built from `engine_network`, finds routes from
+    /// a to c arriving as early as the ones of `searcher`.
+    pub fn assert_same_routes<E: Engine>(engine: &E, searcher: &Searcher) {
+        let (from, to) = ends();
+        let options = SearchOptions::default();
+        let departures = [
+            Timestamp::new(Day::Tuesday, DayTime::new(9, 55)),
+            Timestamp::new(Day::Tuesday, DayTime::new(10, 1)),
+            // catches the night trips on Monday
+            Timestamp::new(Day::Sunday, DayTime::new(23, 40)),
+        ];
+        for &departure in &departures {
+            let expected = Engine::find_route(searcher, from, to, departure, &options).unwrap();
+            let route = engine.find_route(from, to, departure, &options).unwrap();
+            assert_eq!(
+                departure.seconds_until(route.arrival_time),
+                departure.seconds_until(expected.arrival_time)
+            );
+            assert_eq!(route.segments.len(), expected.segments.len());
         }
+    }
 
-        route_segments.reverse();
+    fn bus_segments<'a>(route: &Route<'a>) -> Vec<BusSegment<'a>> {
+        route
+            .segments
+            .iter()
+            .filter_map(|segment| match *segment {
+                Segment::Bus(segment) => Some(segment),
+                Segment::Walk(_) => None,
+            })
+            .collect()
+    }
 
-        let mut route = Route {
-            segments: route_segments,
-            departure_time,
-            arrival_time: arrival_time.time,
+    #[test]
+    fn stops_near_are_sorted_by_distance() {
+        let stops = vec![
+            stop("a", 54.700, 25.200),
+            stop("b", 54.702, 25.200),
+            stop("c", 54.701, 25.200),
+            stop("d", 54.720, 25.200),
+        ];
+        let searcher = Searcher::new(stops, Vec::new());
+        let point = Point {
+            lat: 54.700,
+            lng: 25.200,
         };
+        let near = searcher.stops_near(point, 500.0);
+        let ids = near.iter().map(|&(stop, _)| stop.id).collect::<Vec<_>>();
+        assert_eq!(ids, vec!["a", "c", "b"]);
+        assert!(near[1].1 > 100.0 && near[1].1 < 120.0);
+    }
 
-        self.translate_stop_names(&mut route);
-        self.post_process_route(&mut route);
+    #[test]
+    fn dated_searcher_skips_removed_dates() {
+        let stops = vec![stop("a", 54.70, 25.20), stop("b", 54.71, 25.20)];
+        let mut track = track("A", &["a", "b"], &[DayTime::new(10, 0)], 300);
+        // Wednesday
+        track.timetables[0].removed_dates = vec![Date::new(2018, 7, 4)];
+        let schedules = vec![schedule("1", vec![track])];
+        let monday = Date::new(2018, 7, 2);
+        let searcher = Searcher::with_calendar(stops, schedules, &Calendar::default(), monday);
+        let from = Point {
+            lat: 54.70,
+            lng: 25.20,
+        };
+        let to = Point {
+            lat: 54.71,
+            lng: 25.20,
+        };
+        let options = SearchOptions::default();
+        let departure_on = |date| Timestamp::on_date(date, DayTime::new(9, 55), monday).unwrap();
 
-        Some(route)
+        let route = searcher.find_route(from, to, departure_on(Date::new(2018, 7, 3)), &options);
+        assert_eq!(route.unwrap().arrival_time.day(), Day::Tuesday);
+        let route = searcher.find_route(from, to, departure_on(Date::new(2018, 7, 4)), &options);
+        assert_eq!(route.unwrap().arrival_time.day(), Day::Thursday);
+        assert!(Timestamp::on_date(Date::new(2018, 7, 9), DayTime::new(9, 55), monday).is_none());
     }
 
-    fn translate_stop_names<'a>(&'a self, route: &mut Route<'a>) {
-        for segment in &mut route.segments {
-            match *segment {
-                Segment::Walk(_) => {}
-                Segment::Bus(ref mut segment) => {
-                    segment.from_stop = &self.stops[segment.from_stop].name;
-                    segment.to_stop = &self.stops[segment.to_stop].name;
-                }
-            }
-        }
+    #[test]
+    fn transfers_override_footpaths() {
+        let stops = vec![
+            stop("x", 54.700, 25.200),
+            stop("a", 54.710, 25.200),
+            // across the river from a
+            stop("b", 54.712, 25.200),
+            stop("c", 54.720, 25.200),
+        ];
+        let schedules = vec![
+            schedule(
+                "1",
+                vec![track("A", &["x", "a"], &[DayTime::new(10, 0)], 300)],
+            ),
+            schedule(
+                "2",
+                vec![track("B", &["b", "c"], &[DayTime::new(10, 30)], 300)],
+            ),
+        ];
+        let searcher = Searcher::new(stops, schedules);
+        let from = Point {
+            lat: 54.700,
+            lng: 25.200,
+        };
+        let to = Point {
+            lat: 54.720,
+            lng: 25.200,
+        };
+        let departure = Timestamp::new(Day::Tuesday, DayTime::new(9, 55));
+        let options = SearchOptions::default();
+        let route = searcher.find_route(from, to, departure, &options).unwrap();
+        assert!(route.walk_distance() < 300.0);
+
+        let transfer = |distance| Transfer {
+            from: "a".to_owned(),
+            to: "b".to_owned(),
+            distance,
+        };
+        let footpaths = FootpathOptions {
+            transfers: vec![transfer(None)],
+            ..FootpathOptions::default()
+        };
+        let searcher = searcher.with_footpaths(&footpaths);
+        assert!(searcher.find_route(from, to, departure, &options).is_none());
+
+        let footpaths = FootpathOptions {
+            transfers: vec![transfer(Some(450.0))],
+            ..FootpathOptions::default()
+        };
+        let searcher = searcher.with_footpaths(&footpaths);
+        let route = searcher.find_route(from, to, departure, &options).unwrap();
+        assert_eq!(route.walk_distance(), 450.0);
     }
 
-    fn post_process_route(&self, route: &mut Route) {
-        // join adjacent bus segments that use the same bus
-        route.segments.dedup_by(|b, a| match (a, b) {
-            (&mut Segment::Bus(ref mut a), &mut Segment::Bus(ref mut b)) => {
-                if a.bus != b.bus {
-                    return false;
-                }
-                if a.start.offset(a.duration) != b.start {
-                    return false;
-                }
-                a.duration += b.duration;
-                a.to_stop = b.to_stop;
-                true
-            }
-            _ => false,
-        });
-        // join adjacent walking segments
-        route.segments.dedup_by(|b, a| match (a, b) {
-            (&mut Segment::Walk(ref mut a), &mut Segment::Walk(ref mut b)) => {
-                a.duration += b.duration;
-                a.to = b.to;
-                true
-            }
-            _ => false,
-        });
+    #[test]
+    fn same_line_different_vehicle_is_a_transfer() {
+        let stops = vec![
+            stop("a", 54.70, 25.20),
+            stop("b", 54.71, 25.20),
+            stop("c", 54.72, 25.20),
+            stop("d", 54.71, 25.22),
+        ];
+        // two vehicles of line 3 meet at b at the same time
+        let schedules = vec![schedule(
+            "3",
+            vec![
+                track("A", &["a", "b", "c"], &[DayTime::new(10, 0)], 300),
+                track(
+                    "B",
+                    &["b", "d"],
+                    &[DayTime::new(10, 5), DayTime::new(10, 20)],
+                    300,
+                ),
+            ],
+        )];
+        let searcher = Searcher::new(stops, schedules);
+        let from = Point {
+            lat: 54.70,
+            lng: 25.20,
+        };
+        let to = Point {
+            lat: 54.71,
+            lng: 25.22,
+        };
+        let departure = Timestamp::new(Day::Tuesday, DayTime::new(9, 50));
+        let route = searcher
+            .find_route(from, to, departure, &SearchOptions::default())
+            .unwrap();
+
+        let segments = bus_segments(&route);
+        assert_eq!(segments.len(), 2);
+        assert!(segments[0].trip != segments[1].trip);
+        assert_eq!(segments[1].start.time(), DayTime::new(10, 20));
+    }
+
+    #[test]
+    fn night_bus_across_end_of_week() {
+        let stops = vec![
+            stop("a", 54.70, 25.20),
+            stop("b", 54.71, 25.20),
+            stop("c", 54.72, 25.20),
+        ];
+        let schedules = vec![
+            schedule(
+                "N1",
+                vec![track("A", &["a", "b"], &[DayTime::new(23, 50)], 1200)],
+            ),
+            schedule(
+                "N2",
+                vec![track("B", &["b", "c"], &[DayTime::new(0, 20)], 600)],
+            ),
+        ];
+        let searcher = Searcher::new(stops, schedules);
+        let (from, to) = ends();
+        let departure = Timestamp::new(Day::Sunday, DayTime::new(23, 45));
+        let route = searcher
+            .find_route(from, to, departure, &SearchOptions::default())
+            .unwrap();
+
+        let segments = bus_segments(&route);
+        assert_eq!(segments.len(), 2);
+        assert_eq!(
+            segments[1].start,
+            Timestamp::new(Day::Monday, DayTime::new(0, 20))
+        );
+        assert_eq!(
+            route.arrival_time,
+            Timestamp::new(Day::Monday, DayTime::new(0, 30))
+        );
+        assert_eq!(route.duration(), 45 * 60);
+    }
+
+    #[test]
+    fn options_limit_transfers_and_walking() {
+        let (stops, schedules) = transfer_network(1800);
+        let searcher = Searcher::new(stops, schedules);
+        let (from, to) = ends();
+        let departure = Timestamp::new(Day::Tuesday, DayTime::new(9, 55));
+
+        let mut options = SearchOptions::default();
+        let route = searcher.find_route(from, to, departure, &options).unwrap();
+        assert_eq!(route.transfers(), 1);
+
+        options.max_transfers = Some(0);
+        let route = searcher.find_route(from, to, departure, &options).unwrap();
+        assert_eq!(route.transfers(), 0);
+        assert_eq!(route.arrival_time.time(), DayTime::new(10, 35));
+
+        // the nearest stop is 1.1 km away
+        let to = Point {
+            lat: 54.73,
+            lng: 25.20,
+        };
+        assert!(searcher.find_route(from, to, departure, &options).is_none());
+        options.max_walk_distance = 1200.0;
+        options.max_total_walk = 3000.0;
+        let route = searcher.find_route(from, to, departure, &options).unwrap();
+        assert!(route.walk_distance() > 1100.0);
     }
-}
 
-fn walk_time(distance: f64) -> u64 {
-    // in meters per second
-    let speed = 4.0 * 1000.0 / 3600.0;
-    (distance / speed).ceil() as u64
+    #[test]
+    fn options_filter_lines() {
+        let (stops, schedules) = transfer_network(1200);
+        let searcher = Searcher::new(stops, schedules);
+        let (from, to) = ends();
+        let departure = Timestamp::new(Day::Tuesday, DayTime::new(9, 55));
+        let lines = |options: &SearchOptions| {
+            let route = searcher.find_route(from, to, departure, options);
+            route.map(|route| {
+                bus_segments(&route)
+                    .iter()
+                    .map(|bus| bus.bus)
+                    .collect::<Vec<_>>()
+            })
+        };
+
+        let mut options = SearchOptions::default();
+        assert_eq!(lines(&options), Some(vec!["1", "2"]));
+
+        options.preferred_lines = vec!["3".to_owned()];
+        assert_eq!(lines(&options), Some(vec!["3"]));
+
+        options.preferred_lines.clear();
+        options.banned_lines = vec!["2".to_owned()];
+        assert_eq!(lines(&options), Some(vec!["3"]));
+
+        options.banned_lines.clear();
+        options.allowed_types = Some(vec![TransportType::Trolley]);
+        assert_eq!(lines(&options), None);
+    }
 }
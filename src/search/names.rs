@@ -0,0 +1,358 @@
+use super::{Searcher, StopRef};
+use model::{Point, Stop};
+use std::collections::{BTreeMap, HashMap};
+
+// Added to the cost of query words found only in areas or directions.
+const EXTRA_WORD_COST: usize = 3;
+
+/// Platforms sharing a name.
+#[derive(Serialize, Debug, Clone)]
+pub struct StopMatch<'a> {
+    pub name: &'a str,
+    pub stops: Vec<Platform<'a>>,
+}
+
+/// A stop with its area and direction, telling platforms apart.
+#[derive(Serialize, Debug, Clone)]
+pub struct Platform<'a> {
+    #[serde(flatten)]
+    pub stop: StopRef<'a>,
+    pub area: Option<&'a str>,
+    pub direction: Option<&'a str>,
+}
+
+/// Stop names with their platforms, for matching queries against.
+#[derive(Debug, Clone)]
+pub struct StopNames {
+    groups: Vec<Group>,
+}
+
+#[derive(Debug, Clone)]
+struct Group {
+    name: String,
+    /// The name as folded by `fold`.
+    folded: String,
+    words: Vec<String>,
+    /// Words of areas and directions of the platforms.
+    extra: Vec<String>,
+    ids: Vec<String>,
+}
+
+impl StopNames {
+    pub fn new(stops: &[Stop]) -> StopNames {
+        let mut groups = BTreeMap::<String, Group>::new();
+        for stop in stops {
+            let folded = fold(&stop.name);
+            let group = groups.entry(folded.clone()).or_insert_with(|| Group {
+                name: stop.name.clone(),
+                words: words(&folded),
+                folded,
+                extra: Vec::new(),
+                ids: Vec::new(),
+            });
+            for text in stop.area.iter().chain(&stop.direction) {
+                group.extra.extend(words(&fold(text)));
+            }
+            group.ids.push(stop.id.clone());
+        }
+        let groups = groups
+            .into_values()
+            .map(|mut group| {
+                group.extra.sort();
+                group.extra.dedup();
+                group.ids.sort();
+                group.ids.dedup();
+                group
+            })
+            .collect::<Vec<_>>();
+        debug!("Indexed {} stop names", groups.len());
+        StopNames { groups }
+    }
+
+    /// Names best matching `query` with ids of their platforms, best first.
+    pub fn find(&self, query: &str, limit: usize) -> Vec<(&str, &[String])> {
+        let query = fold(query);
+        let query_words = words(&query);
+        if query_words.is_empty() {
+            return Vec::new();
+        }
+        let mut found = self
+            .groups
+            .iter()
+            .filter_map(|group| Some((group.cost(&query, &query_words)?, group)))
+            .collect::<Vec<_>>();
+        found.sort_by_key(|&(cost, group)| (cost, group.name.len(), &group.name));
+        found
+            .into_iter()
+            .take(limit)
+            .map(|(_, group)| (group.name.as_str(), &group.ids[..]))
+            .collect()
+    }
+
+    /// The stop with id `stop`, or all platforms of the name best matching
+    /// `stop`, as ids. Stops are looked up by id with `find`.
+    fn platforms<'a, F>(&'a self, stop: &str, find: F) -> Vec<&'a str>
+    where
+        F: Fn(&str) -> Option<(&'a str, Point)>,
+    {
+        if let Some((id, _)) = find(stop) {
+            return vec![id];
+        }
+        self.find(stop, 1)
+            .into_iter()
+            .flat_map(|(_, ids)| ids.iter().map(String::as_str))
+            .collect()
+    }
+
+    /// Resolves a location given as coordinates, a stop id or a stop name,
+    /// with stops looked up by id with `find`. A name resolves to the middle
+    /// point of its platforms.
+    fn locate<'a, F>(&'a self, location: &str, find: F) -> Option<Point>
+    where
+        F: Fn(&str) -> Option<(&'a str, Point)>,
+    {
+        if let Ok(point) = location.parse::<Point>() {
+            return Some(point);
+        }
+        let platforms = self
+            .platforms(location, &find)
+            .into_iter()
+            .filter_map(&find)
+            .map(|(_, loc)| loc)
+            .collect::<Vec<_>>();
+        if platforms.is_empty() {
+            return None;
+        }
+        let count = platforms.len() as f64;
+        Some(Point {
+            lat: platforms.iter().map(|loc| loc.lat).sum::<f64>() / count,
+            lng: platforms.iter().map(|loc| loc.lng).sum::<f64>() / count,
+        })
+    }
+}
+
+/// Stop locations by id and name, for resolving locations without building
+/// a searcher.
+#[derive(Debug, Clone)]
+pub struct Locator {
+    locations: HashMap<String, Point>,
+    names: StopNames,
+}
+
+impl Locator {
+    pub fn new(stops: &[Stop]) -> Locator {
+        Locator {
+            locations: stops
+                .iter()
+                .map(|stop| (stop.id.clone(), stop.loc))
+                .collect(),
+            names: StopNames::new(stops),
+        }
+    }
+
+    /// Resolves a location given as coordinates, a stop id or a stop name.
+    /// A name, matched as by `Searcher::find_stops`, resolves to the middle
+    /// point of its platforms.
+    pub fn locate(&self, location: &str) -> Option<Point> {
+        self.names.locate(location, |id| {
+            self.locations
+                .get_key_value(id)
+                .map(|(id, &loc)| (id.as_str(), loc))
+        })
+    }
+}
+
+impl Group {
+    /// How badly the group matches a folded query, `None` if it does not.
+    /// The whole name comes first, then names starting with the query, then
+    /// names with every query word matching a word in them.
+    fn cost(&self, query: &str, query_words: &[String]) -> Option<usize> {
+        if self.folded == query {
+            return Some(0);
+        }
+        if self.folded.starts_with(query) {
+            return Some(1);
+        }
+        let mut total = 2;
+        for word in query_words {
+            let best = |words: &[String]| {
+                words
+                    .iter()
+                    .filter_map(|target| word_cost(word, target))
+                    .min()
+            };
+            total += best(&self.words)
+                .or_else(|| best(&self.extra).map(|cost| cost + EXTRA_WORD_COST))?;
+        }
+        Some(total)
+    }
+}
+
+/// Cost of matching a query word with `target`: none for the same word, 1
+/// for its prefix and more for each typo, which longer words may have more
+/// of.
+fn word_cost(word: &str, target: &str) -> Option<usize> {
+    if word == target {
+        return Some(0);
+    }
+    if target.starts_with(word) {
+        return Some(1);
+    }
+    let length = word.chars().count();
+    let allowed = match length {
+        0..=3 => 0,
+        4..=7 => 1,
+        _ => 2,
+    };
+    let prefix = target.chars().take(length).collect::<String>();
+    let typos = distance(word, target).min(distance(word, &prefix));
+    if typos <= allowed {
+        Some(1 + typos)
+    } else {
+        None
+    }
+}
+
+/// Edit distance, counting a swap of neighbouring letters as one edit.
+fn distance(a: &str, b: &str) -> usize {
+    let a = a.chars().collect::<Vec<_>>();
+    let b = b.chars().collect::<Vec<_>>();
+    let mut rows = vec![(0..=b.len()).collect::<Vec<_>>()];
+    for i in 1..=a.len() {
+        let mut row = vec![i; b.len() + 1];
+        for j in 1..=b.len() {
+            let replace = rows[i - 1][j - 1] + (a[i - 1] != b[j - 1]) as usize;
+            row[j] = replace.min(rows[i - 1][j] + 1).min(row[j - 1] + 1);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                row[j] = row[j].min(rows[i - 2][j - 2] + 1);
+            }
+        }
+        rows.push(row);
+    }
+    rows[a.len()][b.len()]
+}
+
+/// Lowercases `text` and strips Lithuanian diacritics, so that "Žaliasis"
+/// matches "zaliasis".
+fn fold(text: &str) -> String {
+    text.chars()
+        .flat_map(char::to_lowercase)
+        .map(|c| match c {
+            'ą' => 'a',
+            'č' => 'c',
+            'ę' | 'ė' => 'e',
+            'į' => 'i',
+            'š' => 's',
+            'ų' | 'ū' => 'u',
+            'ž' => 'z',
+            c => c,
+        })
+        .collect()
+}
+
+fn words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+impl Searcher {
+    /// Stops named like `query`, best matches first, with platforms sharing
+    /// a name grouped together.
+    pub fn find_stops(&self, query: &str, limit: usize) -> Vec<StopMatch<'_>> {
+        self.names
+            .find(query, limit)
+            .into_iter()
+            .map(|(name, ids)| StopMatch {
+                name,
+                stops: ids
+                    .iter()
+                    .map(|id| {
+                        let stop = &self.stops[id];
+                        Platform {
+                            stop: self.stop_ref(id),
+                            area: stop.area.as_deref(),
+                            direction: stop.direction.as_deref(),
+                        }
+                    })
+                    .collect(),
+            })
+            .collect()
+    }
+
+    /// Resolves a location given as coordinates, a stop id or a stop name,
+    /// see `Locator::locate`.
+    pub fn locate(&self, location: &str) -> Option<Point> {
+        self.names.locate(location, |id| self.location(id))
+    }
+
+    /// The stop with id `stop`, or all platforms of the name best matching
+    /// `stop`, as ids.
+    pub(super) fn platforms(&self, stop: &str) -> Vec<&str> {
+        self.names.platforms(stop, |id| self.location(id))
+    }
+
+    fn location(&self, id: &str) -> Option<(&str, Point)> {
+        self.stops
+            .get_key_value(id)
+            .map(|(id, stop)| (id.as_str(), stop.loc))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::named_stop;
+    use super::*;
+
+    #[test]
+    fn fold_diacritics_and_typos() {
+        assert_eq!(fold("Žaliasis tiltas"), "zaliasis tiltas");
+        assert_eq!(distance("tilats", "tiltas"), 1);
+        assert_eq!(word_cost("zal", "zaliasis"), Some(1));
+        assert_eq!(word_cost("zalaisis", "zaliasis"), Some(2));
+        assert_eq!(word_cost("tlt", "tiltas"), None);
+    }
+
+    #[test]
+    fn find_stops_by_name() {
+        let names = [
+            ("a1", "Žaliasis tiltas", "Kalvarijų turgus"),
+            ("a2", "Žaliasis tiltas", "Vinco Kudirkos aikštė"),
+            ("b", "Žalgirio stotelė", "Centras"),
+            ("c", "Tilto g.", "Žvėrynas"),
+        ];
+        let stops = names
+            .iter()
+            .map(|&(id, name, direction)| Stop {
+                direction: Some(direction.to_owned()),
+                ..named_stop(id, name, 54.70, 25.20)
+            })
+            .collect();
+        let searcher = Searcher::new(stops, Vec::new());
+        let find = |query| {
+            searcher
+                .find_stops(query, 10)
+                .into_iter()
+                .map(|found| found.name)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(find("zaliasis tiltas"), vec!["Žaliasis tiltas"]);
+        assert_eq!(find("žal"), vec!["Žaliasis tiltas", "Žalgirio stotelė"]);
+        assert_eq!(find("zalaisis"), vec!["Žaliasis tiltas"]);
+        assert_eq!(find("tilt"), vec!["Tilto g.", "Žaliasis tiltas"]);
+        assert_eq!(find("zverynas"), vec!["Tilto g."]);
+        assert!(find("stotis").is_empty());
+
+        let found = searcher.find_stops("Zaliasis tiltas", 1);
+        let ids = found[0]
+            .stops
+            .iter()
+            .map(|platform| platform.stop.id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec!["a1", "a2"]);
+        assert_eq!(found[0].stops[1].direction, Some("Vinco Kudirkos aikštė"));
+        assert!(searcher.locate("zaliasis tiltas").is_some());
+    }
+}
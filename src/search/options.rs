@@ -0,0 +1,147 @@
+use model::TransportType;
+
+/// Per-query routing parameters.
+#[derive(Debug, Clone)]
+pub struct SearchOptions {
+    /// Walking speed, in meters per second.
+    pub walk_speed: f64,
+    /// Longest single walk, in meters.
+    pub max_walk_distance: f64,
+    /// Longest total walk over the whole route, in meters.
+    pub max_total_walk: f64,
+    /// Time needed to board a vehicle after reaching a stop, in seconds.
+    pub transfer_time: u64,
+    /// Extra cost of boarding a vehicle, in seconds of travel time.
+    pub transfer_penalty: u64,
+    /// Most vehicle changes in a route, `None` for no limit.
+    pub max_transfers: Option<u64>,
+    /// Transport types that may be used, `None` for all of them.
+    pub allowed_types: Option<Vec<TransportType>>,
+    /// Transport types that must not be used.
+    pub denied_types: Vec<TransportType>,
+    /// Names of lines that must not be used.
+    pub banned_lines: Vec<String>,
+    /// Names of lines to ride if reasonably possible. If not empty, boarding
+    /// any other line costs `other_line_penalty` seconds extra.
+    pub preferred_lines: Vec<String>,
+    /// Extra cost of boarding a line that is not preferred, in seconds of
+    /// travel time.
+    pub other_line_penalty: u64,
+    /// Pareto searches drop routes taking more than this many times as long
+    /// as the fastest one, `None` to keep every Pareto-optimal route.
+    pub max_slowdown: Option<f64>,
+}
+
+impl Default for SearchOptions {
+    fn default() -> SearchOptions {
+        SearchOptions {
+            walk_speed: 4.0 * 1000.0 / 3600.0,
+            max_walk_distance: 500.0,
+            max_total_walk: 2000.0,
+            transfer_time: 3 * 60,
+            transfer_penalty: 60,
+            max_transfers: None,
+            allowed_types: None,
+            denied_types: Vec::new(),
+            banned_lines: Vec::new(),
+            preferred_lines: Vec::new(),
+            other_line_penalty: 5 * 60,
+            max_slowdown: None,
+        }
+    }
+}
+
+impl SearchOptions {
+    /// Checks that speeds are positive and distances are not negative, all
+    /// of them finite, describing the first invalid option otherwise.
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.walk_speed.is_finite() && self.walk_speed > 0.0) {
+            return Err(format!("invalid walk speed: {}", self.walk_speed));
+        }
+        let distances = [
+            ("max walk distance", self.max_walk_distance),
+            ("max total walk", self.max_total_walk),
+        ];
+        for &(name, distance) in &distances {
+            if !(distance.is_finite() && distance >= 0.0) {
+                return Err(format!("invalid {}: {}", name, distance));
+            }
+        }
+        match self.max_slowdown {
+            Some(slowdown) if !(slowdown.is_finite() && slowdown >= 1.0) => {
+                Err(format!("invalid max slowdown: {}", slowdown))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Time to walk `distance` meters, in seconds.
+    pub fn walk_time(&self, distance: f64) -> u64 {
+        (distance / self.walk_speed).ceil() as u64
+    }
+
+    /// Whether a route may board another vehicle after `rides` ones.
+    pub fn may_board(&self, rides: u64) -> bool {
+        match self.max_transfers {
+            Some(max) => rides <= max,
+            None => true,
+        }
+    }
+
+    /// Whether vehicles of `line` may be used.
+    pub fn allows(&self, line: &str, typ: TransportType) -> bool {
+        let type_allowed = match self.allowed_types {
+            Some(ref types) => types.contains(&typ),
+            None => true,
+        };
+        type_allowed && !self.denied_types.contains(&typ) && !is_listed(&self.banned_lines, line)
+    }
+
+    /// Cost of boarding a vehicle of `line`, in seconds of travel time.
+    pub fn boarding_penalty(&self, line: &str) -> u64 {
+        if self.preferred_lines.is_empty() || is_listed(&self.preferred_lines, line) {
+            self.transfer_penalty
+        } else {
+            self.transfer_penalty + self.other_line_penalty
+        }
+    }
+}
+
+fn is_listed(lines: &[String], line: &str) -> bool {
+    lines.iter().any(|name| name.eq_ignore_ascii_case(line))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_options() {
+        assert!(SearchOptions::default().validate().is_ok());
+        let invalid = [
+            SearchOptions {
+                walk_speed: 0.0,
+                ..SearchOptions::default()
+            },
+            SearchOptions {
+                walk_speed: f64::NAN,
+                ..SearchOptions::default()
+            },
+            SearchOptions {
+                max_walk_distance: -1.0,
+                ..SearchOptions::default()
+            },
+            SearchOptions {
+                max_total_walk: f64::INFINITY,
+                ..SearchOptions::default()
+            },
+            SearchOptions {
+                max_slowdown: Some(0.5),
+                ..SearchOptions::default()
+            },
+        ];
+        for options in &invalid {
+            assert!(options.validate().is_err(), "{:?}", options);
+        }
+    }
+}
@@ -0,0 +1,395 @@
+use super::{post_process_route, SearchOptions, Searcher};
+use model::{BusSegment, NamedPoint, Point, Route, Segment, Timestamp, WalkSegment};
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+// Routes are only considered if they depart at most this long after
+// reaching the stop, in seconds.
+const MAX_WAIT: u64 = 2 * 24 * 3600;
+
+/// A way of reaching a stop.
+struct Label<'a> {
+    stop: &'a str,
+    arrival: Timestamp,
+    /// Seconds since the departure.
+    elapsed: u64,
+    rides: u64,
+    /// Walked distance, in meters.
+    walked: f64,
+    /// Index of the label this one continues.
+    parent: Option<usize>,
+    segment: Segment<'a>,
+}
+
+impl<'a> Label<'a> {
+    fn dominates(&self, other: &Label) -> bool {
+        self.elapsed <= other.elapsed && self.rides <= other.rides && self.walked <= other.walked
+    }
+
+    /// Whether this label makes `other`, reaching the same stop, useless.
+    /// A label arriving by bus can stay on it without a transfer, so only
+    /// labels arriving on the same trip can replace it.
+    fn replaces(&self, other: &Label) -> bool {
+        let same_vehicle = match (self.segment, other.segment) {
+            (_, Segment::Walk(_)) => true,
+            (Segment::Bus(a), Segment::Bus(b)) => a.trip == b.trip,
+            (Segment::Walk(_), Segment::Bus(_)) => false,
+        };
+        same_vehicle && self.dominates(other)
+    }
+}
+
+/// A finished route, ending with the walk from `label`.
+struct Arrival {
+    label: usize,
+    elapsed: u64,
+    rides: u64,
+    walked: f64,
+}
+
+impl Arrival {
+    fn dominates(&self, other: &Arrival) -> bool {
+        self.elapsed <= other.elapsed && self.rides <= other.rides && self.walked <= other.walked
+    }
+}
+
+struct HeapItem {
+    elapsed: u64,
+    label: usize,
+}
+
+impl Ord for HeapItem {
+    fn cmp(&self, other: &HeapItem) -> Ordering {
+        // we want earliest items to come first, so they must be greatest
+        (self.elapsed, self.label)
+            .cmp(&(other.elapsed, other.label))
+            .reverse()
+    }
+}
+
+impl PartialOrd for HeapItem {
+    fn partial_cmp(&self, other: &HeapItem) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for HeapItem {
+    fn eq(&self, other: &HeapItem) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for HeapItem {}
+
+impl Searcher {
+    /// Finds routes from `from` to `to` such that none of them arrives
+    /// later, transfers more and walks more than another one. Routes are
+    /// sorted by arrival time. Penalties in `options` are not used, since
+    /// transfers are a criterion of their own, and slow routes are only
+    /// dropped if `options.max_slowdown` is set.
+    pub fn find_pareto_routes(
+        &self,
+        from: Point,
+        to: Point,
+        departure: Timestamp,
+        options: &SearchOptions,
+    ) -> Vec<Route<'_>> {
+        let mut labels = Vec::new();
+        let mut queue = BinaryHeap::new();
+        let egress = self.walks(to, options.max_walk_distance);
+        let can_walk = |walked: f64, distance: f64| {
+            distance <= options.max_walk_distance && walked + distance <= options.max_total_walk
+        };
+
+        for (id, distance) in self.walking.within(from, options.max_walk_distance) {
+            if !can_walk(0.0, distance) {
+                continue;
+            }
+            let walk_time = options.walk_time(distance);
+            queue.push(HeapItem {
+                elapsed: walk_time,
+                label: labels.len(),
+            });
+            labels.push(Label {
+                stop: id,
+                arrival: departure.offset(walk_time),
+                elapsed: walk_time,
+                rides: 0,
+                walked: distance,
+                parent: None,
+                segment: Segment::Walk(WalkSegment {
+                    from: NamedPoint {
+                        id: None,
+                        name: None,
+                        loc: from,
+                    },
+                    to: self.stop_point(id),
+                    start: departure,
+                    duration: walk_time,
+                    distance,
+                }),
+            });
+        }
+
+        let mut bags = HashMap::<&str, Vec<usize>>::new();
+        let mut arrivals = Vec::<Arrival>::new();
+        let mut limit = u64::MAX;
+
+        while let Some(HeapItem {
+            elapsed,
+            label: index,
+        }) = queue.pop()
+        {
+            if elapsed > limit {
+                break;
+            }
+            let mut new_labels = Vec::new();
+            {
+                let label = &labels[index];
+                let bag = bags.entry(label.stop).or_default();
+                if bag.iter().any(|&other| labels[other].replaces(label)) {
+                    continue;
+                }
+                let reached = Arrival {
+                    label: index,
+                    elapsed: label.elapsed,
+                    rides: label.rides,
+                    walked: label.walked,
+                };
+                if arrivals.iter().any(|arrival| arrival.dominates(&reached)) {
+                    continue;
+                }
+                bag.push(index);
+                trace!(
+                    "Reached stop {} at {} ({} rides, {:.0} m walked)",
+                    label.stop,
+                    label.arrival,
+                    label.rides,
+                    label.walked,
+                );
+                let stop = &self.stops[label.stop];
+
+                // walk to the destination
+                let to_end = egress
+                    .get(label.stop)
+                    .cloned()
+                    .filter(|&distance| can_walk(label.walked, distance));
+                if let Some(distance) = to_end {
+                    let arrival = Arrival {
+                        elapsed: label.elapsed + options.walk_time(distance),
+                        walked: label.walked + distance,
+                        ..reached
+                    };
+                    if !arrivals.iter().any(|other| other.dominates(&arrival)) {
+                        arrivals.retain(|other| !arrival.dominates(other));
+                        if let Some(slowdown) = options.max_slowdown {
+                            limit = limit.min((arrival.elapsed as f64 * slowdown) as u64);
+                        }
+                        arrivals.push(arrival);
+                    }
+                }
+
+                // take the first departure of every line to every next stop
+                let trip = match label.segment {
+                    Segment::Bus(segment) => Some(segment.trip),
+                    Segment::Walk(_) => None,
+                };
+                let start = stop
+                    .routes
+                    .partition_point(|route| route.departure < label.arrival);
+                let (earlier, later) = stop.routes.split_at(start);
+                let mut seen = HashSet::new();
+                for route in later.iter().chain(earlier) {
+                    let wait = label.arrival.seconds_until(route.departure);
+                    if wait > MAX_WAIT || label.elapsed + wait > limit {
+                        break;
+                    }
+                    let stays = trip == Some(route.trip);
+                    if !stays {
+                        if !options.allows(&route.bus, route.typ) {
+                            continue;
+                        }
+                        if wait < options.transfer_time || !options.may_board(label.rides) {
+                            continue;
+                        }
+                        if !seen.insert((&route.bus, &route.next_stop)) {
+                            continue;
+                        }
+                    }
+                    new_labels.push(Label {
+                        stop: &route.next_stop,
+                        arrival: route.arrival,
+                        elapsed: label.elapsed + wait + route.duration,
+                        rides: label.rides + !stays as u64,
+                        walked: label.walked,
+                        parent: Some(index),
+                        segment: Segment::Bus(BusSegment {
+                            bus: &route.bus,
+                            trip: route.trip,
+                            typ: route.typ,
+                            from: self.stop_point(label.stop),
+                            to: self.stop_point(&route.next_stop),
+                            start: route.departure,
+                            duration: route.duration,
+                        }),
+                    });
+                }
+
+                // walk to nearby stops after getting off a bus
+                if trip.is_some() {
+                    for &(ref id, distance) in &stop.footpaths {
+                        if !can_walk(label.walked, distance) {
+                            continue;
+                        }
+                        let walk_time = options.walk_time(distance);
+                        new_labels.push(Label {
+                            stop: id,
+                            arrival: label.arrival.offset(walk_time),
+                            elapsed: label.elapsed + walk_time,
+                            rides: label.rides,
+                            walked: label.walked + distance,
+                            parent: Some(index),
+                            segment: Segment::Walk(WalkSegment {
+                                from: self.stop_point(label.stop),
+                                to: self.stop_point(id),
+                                start: label.arrival,
+                                duration: walk_time,
+                                distance,
+                            }),
+                        });
+                    }
+                }
+            }
+            for label in new_labels {
+                queue.push(HeapItem {
+                    elapsed: label.elapsed,
+                    label: labels.len(),
+                });
+                labels.push(label);
+            }
+        }
+
+        arrivals.sort_by_key(|arrival| (arrival.elapsed, arrival.rides));
+        debug!("Found {} Pareto optimal routes", arrivals.len());
+        arrivals
+            .iter()
+            .map(|arrival| self.build_route(&labels, arrival, departure, (to, &egress), options))
+            .collect()
+    }
+
+    fn build_route<'a>(
+        &'a self,
+        labels: &[Label<'a>],
+        arrival: &Arrival,
+        departure: Timestamp,
+        (to, egress): (Point, &HashMap<&str, f64>),
+        options: &SearchOptions,
+    ) -> Route<'a> {
+        let last = &labels[arrival.label];
+        let distance = egress[last.stop];
+        let mut segments = vec![Segment::Walk(WalkSegment {
+            from: self.stop_point(last.stop),
+            to: NamedPoint {
+                id: None,
+                name: None,
+                loc: to,
+            },
+            start: last.arrival,
+            duration: options.walk_time(distance),
+            distance,
+        })];
+        let mut current = Some(arrival.label);
+        while let Some(index) = current {
+            segments.push(labels[index].segment);
+            current = labels[index].parent;
+        }
+        segments.reverse();
+
+        let mut route = Route {
+            segments,
+            departure_time: departure,
+            arrival_time: departure.offset(arrival.elapsed),
+        };
+        post_process_route(&mut route);
+        route
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{ends, schedule, stop, track, transfer_network};
+    use super::*;
+    use model::{Day, DayTime};
+
+    #[test]
+    fn faster_route_with_transfer_and_slower_direct_one() {
+        let (stops, schedules) = transfer_network(1800);
+        let searcher = Searcher::new(stops, schedules);
+        let (from, to) = ends();
+        let departure = Timestamp::new(Day::Monday, DayTime::new(9, 55));
+        let routes = searcher.find_pareto_routes(from, to, departure, &SearchOptions::default());
+
+        let summary = routes
+            .iter()
+            .map(|route| (route.arrival_time.time(), route.transfers()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![(DayTime::new(10, 15), 1), (DayTime::new(10, 35), 0)]
+        );
+    }
+
+    #[test]
+    fn slow_direct_route_is_kept() {
+        let stops = vec![
+            stop("a", 54.70, 25.20),
+            stop("b", 54.71, 25.20),
+            stop("c", 54.72, 25.20),
+            stop("d", 54.73, 25.20),
+        ];
+        let schedules = vec![
+            schedule(
+                "1",
+                vec![track("A", &["a", "b"], &[DayTime::new(10, 0)], 300)],
+            ),
+            schedule(
+                "2",
+                vec![track("B", &["b", "c"], &[DayTime::new(10, 10)], 300)],
+            ),
+            schedule(
+                "3",
+                vec![track("C", &["c", "d"], &[DayTime::new(10, 20)], 300)],
+            ),
+            schedule(
+                "4",
+                vec![track("D", &["a", "d"], &[DayTime::new(10, 5)], 4800)],
+            ),
+        ];
+        let searcher = Searcher::new(stops, schedules);
+        let from = Point {
+            lat: 54.70,
+            lng: 25.20,
+        };
+        let to = Point {
+            lat: 54.73,
+            lng: 25.20,
+        };
+        let departure = Timestamp::new(Day::Monday, DayTime::new(9, 55));
+        let mut options = SearchOptions::default();
+        let summary = |options: &SearchOptions| {
+            searcher
+                .find_pareto_routes(from, to, departure, options)
+                .iter()
+                .map(|route| (route.arrival_time.time(), route.transfers()))
+                .collect::<Vec<_>>()
+        };
+
+        // the direct route takes three times as long as the fastest one
+        assert_eq!(
+            summary(&options),
+            vec![(DayTime::new(10, 25), 2), (DayTime::new(11, 25), 0)]
+        );
+        options.max_slowdown = Some(2.0);
+        assert_eq!(summary(&options), vec![(DayTime::new(10, 25), 2)]);
+    }
+}
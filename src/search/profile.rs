@@ -0,0 +1,115 @@
+use super::{SearchOptions, Searcher};
+use model::{Point, Route, Segment, Timestamp};
+
+// Upper bound on searches done for a single profile query.
+const MAX_SEARCHES: usize = 500;
+// How far to move the departure time after a route that needs no bus, in
+// seconds.
+const WALK_STEP: u64 = 60;
+
+impl Searcher {
+    /// Finds journeys from `from` to `to` departing between `earliest` and
+    /// `latest`, such that no other journey leaves later and arrives earlier.
+    /// Journeys are sorted by departure time and leave as late as possible
+    /// to catch their first bus.
+    pub fn find_profile(
+        &self,
+        from: Point,
+        to: Point,
+        earliest: Timestamp,
+        latest: Timestamp,
+        options: &SearchOptions,
+    ) -> Vec<Route<'_>> {
+        let window = earliest.seconds_until(latest);
+        let mut routes = Vec::new();
+        let mut departure = earliest;
+
+        for _ in 0..MAX_SEARCHES {
+            let mut route = match self.find_route(from, to, departure, options) {
+                Some(route) => route,
+                None => break,
+            };
+            let rides_bus = leave_as_late_as_possible(&mut route, options.transfer_time);
+            let leaves_at = earliest.seconds_until(route.departure_time);
+            if leaves_at > window {
+                break;
+            }
+            departure = if rides_bus {
+                route.departure_time.offset(1)
+            } else {
+                route.departure_time.offset(WALK_STEP)
+            };
+            routes.push(route);
+        }
+
+        // keep routes that are not dominated by ones leaving later
+        let mut best_arrival = u64::MAX;
+        let mut useful = Vec::new();
+        for route in routes.into_iter().rev() {
+            let arrival = earliest.seconds_until(route.arrival_time);
+            if arrival < best_arrival {
+                best_arrival = arrival;
+                useful.push(route);
+            }
+        }
+        useful.reverse();
+        debug!("Found {} journeys in profile", useful.len());
+        useful
+    }
+}
+
+/// Delays the walk from the origin so that the first bus is caught just in
+/// time, `transfer_time` seconds before it leaves. Returns whether the route
+/// uses a bus at all.
+fn leave_as_late_as_possible(route: &mut Route, transfer_time: u64) -> bool {
+    let first_bus_start = route.segments.iter().find_map(|segment| match *segment {
+        Segment::Bus(ref bus) => Some(bus.start),
+        Segment::Walk(_) => None,
+    });
+    let first_bus_start = match first_bus_start {
+        Some(start) => start,
+        None => return false,
+    };
+    if let Segment::Walk(ref mut walk) = route.segments[0] {
+        walk.start = first_bus_start.neg_offset(transfer_time + walk.duration);
+        route.departure_time = walk.start;
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{ends, schedule, stop, track};
+    use super::*;
+    use model::{Day, DayTime};
+
+    #[test]
+    fn departures_in_window() {
+        let stops = vec![stop("a", 54.70, 25.20), stop("b", 54.72, 25.20)];
+        let departures = (14..20)
+            .map(|half_hours| DayTime::new(half_hours / 2, half_hours % 2 * 30))
+            .collect::<Vec<_>>();
+        let schedules = vec![schedule(
+            "1",
+            vec![track("A", &["a", "b"], &departures, 600)],
+        )];
+        let searcher = Searcher::new(stops, schedules);
+        let (from, to) = ends();
+        let earliest = Timestamp::new(Day::Monday, DayTime::new(7, 0));
+        let latest = Timestamp::new(Day::Monday, DayTime::new(8, 0));
+        let options = SearchOptions::default();
+        let routes = searcher.find_profile(from, to, earliest, latest, &options);
+
+        let times = routes
+            .iter()
+            .map(|route| (route.departure_time.time(), route.arrival_time.time()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            times,
+            vec![
+                (DayTime::new(7, 27), DayTime::new(7, 40)),
+                (DayTime::new(7, 57), DayTime::new(8, 10)),
+            ]
+        );
+    }
+}
@@ -0,0 +1,569 @@
+use super::walking::Walking;
+use super::{
+    footpaths, post_process_route, Engine, FootpathOptions, SearchOptions, Service, DAY, WEEK,
+};
+use model::{
+    BusSegment, Calendar, Date, NamedPoint, Point, Route, Schedule, Segment, Stop as MStop,
+    Timestamp, TransportType, TripId, WalkSegment, DAYS, MAX_WAIT,
+};
+use std::collections::HashMap;
+
+/// Searcher using RAPTOR, built from the same data as `Searcher`. Round `k`
+/// finds the earliest arrival at each stop using `k` vehicles.
+pub struct Raptor {
+    stops: Vec<Stop>,
+    patterns: Vec<Pattern>,
+    /// Indices of stops, by walking distance.
+    walking: Walking<usize>,
+}
+
+struct Stop {
+    id: String,
+    name: String,
+    loc: Point,
+    /// Patterns serving this stop, with the stop's position in each.
+    patterns: Vec<(usize, usize)>,
+    /// Nearby stops with distances to them.
+    footpaths: Vec<(usize, f64)>,
+}
+
+struct Pattern {
+    bus: String,
+    typ: TransportType,
+    stops: Vec<usize>,
+    /// Sorted by time at every stop, as trips overtaking others are split
+    /// into patterns of their own.
+    trips: Vec<Trip>,
+}
+
+struct Trip {
+    id: TripId,
+    /// Seconds since the start of the week at each stop of the pattern.
+    times: Vec<u64>,
+}
+
+#[derive(Debug, Copy, Clone)]
+enum Parent {
+    /// Walked from the origin.
+    Access { distance: f64 },
+    /// Rode a trip boarded from the label of `round`.
+    Ride {
+        round: usize,
+        pattern: usize,
+        trip: usize,
+        boarded: usize,
+        alighted: usize,
+    },
+    /// Walked from a stop reached by a ride in `round`.
+    Walk {
+        round: usize,
+        from: usize,
+        distance: f64,
+    },
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Label {
+    arrival: u64,
+    walked: f64,
+    parent: Parent,
+}
+
+/// Labels after a round.
+struct Round {
+    /// Stops reached by a ride in this round, which walks start from.
+    rides: Vec<Option<Label>>,
+    /// Best labels so far, including walks and earlier rounds.
+    labels: Vec<Option<Label>>,
+}
+
+impl Raptor {
+    pub fn new(stops: Vec<MStop>, schedules: Vec<Schedule>) -> Raptor {
+        Raptor::build(
+            stops,
+            schedules,
+            Service::Weekly,
+            &FootpathOptions::default(),
+        )
+    }
+
+    /// Builds a searcher for the week starting at `week_start`, like
+    /// `Searcher::with_calendar`.
+    pub fn with_calendar(
+        stops: Vec<MStop>,
+        schedules: Vec<Schedule>,
+        calendar: &Calendar,
+        week_start: Date,
+    ) -> Raptor {
+        Raptor::build(
+            stops,
+            schedules,
+            Service::Dated {
+                calendar,
+                week_start,
+            },
+            &FootpathOptions::default(),
+        )
+    }
+
+    /// Builds a searcher running timetables by `service`, with walking
+    /// connections found using `footpaths`, like `Searcher::build`.
+    pub fn build(
+        stops: Vec<MStop>,
+        schedules: Vec<Schedule>,
+        service: Service,
+        footpaths: &FootpathOptions,
+    ) -> Raptor {
+        let mut stops = stops
+            .into_iter()
+            .map(|stop| Stop {
+                id: stop.id,
+                name: stop.name,
+                loc: stop.loc,
+                patterns: Vec::new(),
+                footpaths: Vec::new(),
+            })
+            .collect::<Vec<_>>();
+        let ids = stops
+            .iter()
+            .enumerate()
+            .map(|(index, stop)| (stop.id.clone(), index))
+            .collect::<HashMap<_, _>>();
+
+        let mut patterns = Vec::new();
+        for (schedule_index, schedule) in schedules.iter().enumerate() {
+            for (track_index, track) in schedule.tracks.iter().enumerate() {
+                let mut trips = Vec::new();
+                for (day_index, &day) in DAYS.iter().enumerate() {
+                    let day_start = day_index as u64 * DAY;
+                    for (tt_index, tt) in track.timetables.iter().enumerate() {
+                        if !service.runs(tt, day) {
+                            continue;
+                        }
+                        for (dep_index, time) in tt.departure_times().enumerate() {
+                            let id = TripId {
+                                schedule: schedule_index,
+                                track: track_index,
+                                timetable: tt_index,
+                                departure: dep_index,
+                                day,
+                            };
+                            let times = (0..track.stops.len())
+                                .map(|index| day_start + tt.find_stop_time(index, time).raw)
+                                .collect::<Vec<_>>();
+                            // trips late in the week are also caught from the
+                            // start of the next one
+                            trips.push(Trip {
+                                id,
+                                times: times.iter().map(|time| time + WEEK).collect(),
+                            });
+                            trips.push(Trip { id, times });
+                        }
+                    }
+                }
+                if trips.is_empty() {
+                    continue;
+                }
+                let pattern_stops = track
+                    .stops
+                    .iter()
+                    .map(|id| *ids.get(id).expect("schedule refers to non-existing stop"))
+                    .collect::<Vec<_>>();
+                for trips in split_overtaking(trips) {
+                    for (position, &stop) in pattern_stops.iter().enumerate() {
+                        stops[stop].patterns.push((patterns.len(), position));
+                    }
+                    patterns.push(Pattern {
+                        bus: schedule.name.clone(),
+                        typ: schedule.transport_type,
+                        stops: pattern_stops.clone(),
+                        trips,
+                    });
+                }
+            }
+        }
+
+        debug!(
+            "Built RAPTOR network: {} stops, {} patterns",
+            stops.len(),
+            patterns.len()
+        );
+        let mut raptor = Raptor {
+            stops,
+            patterns,
+            walking: Walking::new(Vec::new(), None),
+        };
+        raptor.set_footpaths(footpaths);
+        raptor
+    }
+
+    /// Replaces walking connections between stops, like
+    /// `Searcher::with_footpaths`.
+    pub fn with_footpaths(mut self, options: &FootpathOptions) -> Raptor {
+        self.set_footpaths(options);
+        self
+    }
+
+    fn set_footpaths(&mut self, options: &FootpathOptions) {
+        let stops = self.stops.iter().map(|stop| stop.loc).zip(0..).collect();
+        self.walking = Walking::new(stops, options.streets.as_ref());
+        let paths = {
+            let ids = self
+                .stops
+                .iter()
+                .map(|stop| stop.id.as_str())
+                .collect::<Vec<_>>();
+            footpaths(&self.walking, &ids, options)
+        };
+        for (stop, paths) in self.stops.iter_mut().zip(paths) {
+            stop.footpaths = paths;
+        }
+    }
+
+    fn stop_point(&self, stop: usize) -> NamedPoint<'_> {
+        let stop = &self.stops[stop];
+        NamedPoint {
+            id: Some(&stop.id),
+            name: Some(&stop.name),
+            loc: stop.loc,
+        }
+    }
+
+    /// Runs the rounds, returning labels of every round.
+    fn rounds(
+        &self,
+        from: Point,
+        start: u64,
+        egress: &[(usize, f64)],
+        options: &SearchOptions,
+    ) -> Vec<Round> {
+        let count = self.stops.len();
+        let max_walk = options.max_walk_distance;
+        let mut best = vec![u64::MAX; count];
+        let mut first_round = vec![None; count];
+        let mut marked = Vec::new();
+        for (&index, distance) in self.walking.within(from, max_walk) {
+            if distance > options.max_total_walk {
+                continue;
+            }
+            let arrival = start + options.walk_time(distance);
+            first_round[index] = Some(Label {
+                arrival,
+                walked: distance,
+                parent: Parent::Access { distance },
+            });
+            best[index] = arrival;
+            marked.push(index);
+        }
+        let mut rounds = vec![Round {
+            rides: vec![None; count],
+            labels: first_round,
+        }];
+        // earliest arrival at the destination found so far
+        let mut target = u64::MAX;
+
+        while !marked.is_empty() && options.may_board(rounds.len() as u64 - 1) {
+            let round = rounds.len() - 1;
+            let previous = &rounds[round].labels;
+            let mut current = previous.clone();
+            let mut rides = vec![None; count];
+
+            // scan each pattern from the first stop reached in the last round
+            let mut scanned = HashMap::<usize, usize>::new();
+            for &stop in &marked {
+                for &(pattern, position) in &self.stops[stop].patterns {
+                    let first = scanned.entry(pattern).or_insert(position);
+                    *first = (*first).min(position);
+                }
+            }
+            let mut improved = Vec::new();
+            for (&pattern_index, &first) in &scanned {
+                let pattern = &self.patterns[pattern_index];
+                if !options.allows(&pattern.bus, pattern.typ) {
+                    continue;
+                }
+                // trip being ridden and where it was boarded
+                let mut riding: Option<(usize, usize)> = None;
+                for position in first..pattern.stops.len() {
+                    let stop = pattern.stops[position];
+                    if let Some((trip, boarded)) = riding {
+                        let arrival = pattern.trips[trip].times[position];
+                        if arrival < best[stop].min(target) {
+                            let boarded_label = previous[pattern.stops[boarded]]
+                                .expect("boarded at unreached stop");
+                            let label = Label {
+                                arrival,
+                                walked: boarded_label.walked,
+                                parent: Parent::Ride {
+                                    round,
+                                    pattern: pattern_index,
+                                    trip,
+                                    boarded,
+                                    alighted: position,
+                                },
+                            };
+                            rides[stop] = Some(label);
+                            current[stop] = Some(label);
+                            best[stop] = arrival;
+                            improved.push(stop);
+                        }
+                    }
+                    // an earlier trip may be caught here
+                    if let Some(label) = previous[stop] {
+                        let ready = label.arrival + options.transfer_time;
+                        let trip = pattern
+                            .trips
+                            .partition_point(|trip| trip.times[position] < ready);
+                        if trip == pattern.trips.len()
+                            || pattern.trips[trip].times[position] - ready > MAX_WAIT
+                        {
+                            continue;
+                        }
+                        let is_earlier = match riding {
+                            Some((current, _)) => {
+                                pattern.trips[trip].times[position]
+                                    < pattern.trips[current].times[position]
+                            }
+                            None => true,
+                        };
+                        if is_earlier {
+                            riding = Some((trip, position));
+                        }
+                    }
+                }
+            }
+
+            // walk from stops reached by a ride
+            improved.sort();
+            improved.dedup();
+            let mut next_marked = improved.clone();
+            for &stop in &improved {
+                let label = rides[stop].expect("improved stop has no ride");
+                for &(next, distance) in &self.stops[stop].footpaths {
+                    if distance > max_walk || label.walked + distance > options.max_total_walk {
+                        continue;
+                    }
+                    let arrival = label.arrival + options.walk_time(distance);
+                    if arrival < best[next].min(target) {
+                        current[next] = Some(Label {
+                            arrival,
+                            walked: label.walked + distance,
+                            parent: Parent::Walk {
+                                round: rounds.len(),
+                                from: stop,
+                                distance,
+                            },
+                        });
+                        best[next] = arrival;
+                        next_marked.push(next);
+                    }
+                }
+            }
+            next_marked.sort();
+            next_marked.dedup();
+
+            for &(stop, distance) in egress {
+                if let Some(label) = current[stop] {
+                    if label.walked + distance <= options.max_total_walk {
+                        target = target.min(label.arrival + options.walk_time(distance));
+                    }
+                }
+            }
+            marked = next_marked;
+            rounds.push(Round {
+                rides,
+                labels: current,
+            });
+        }
+        rounds
+    }
+
+    /// Follows labels back from `stop` in `round`, returning the segments.
+    fn segments(
+        &self,
+        rounds: &[Round],
+        round: usize,
+        mut stop: usize,
+        from: Point,
+        departure: Timestamp,
+    ) -> Vec<Segment<'_>> {
+        let mut segments = Vec::new();
+        let mut label = rounds[round].labels[stop].expect("route ends at unreached stop");
+        loop {
+            match label.parent {
+                Parent::Access { distance } => {
+                    segments.push(Segment::Walk(WalkSegment {
+                        from: NamedPoint {
+                            id: None,
+                            name: None,
+                            loc: from,
+                        },
+                        to: self.stop_point(stop),
+                        start: departure,
+                        duration: label.arrival - departure.week_seconds(),
+                        distance,
+                    }));
+                    break;
+                }
+                Parent::Ride {
+                    round,
+                    pattern,
+                    trip,
+                    boarded,
+                    alighted,
+                } => {
+                    let pattern_ref = &self.patterns[pattern];
+                    let trip = &pattern_ref.trips[trip];
+                    let boarded_stop = pattern_ref.stops[boarded];
+                    segments.push(Segment::Bus(BusSegment {
+                        bus: &pattern_ref.bus,
+                        trip: trip.id,
+                        typ: pattern_ref.typ,
+                        from: self.stop_point(boarded_stop),
+                        to: self.stop_point(stop),
+                        start: Timestamp::from_week_seconds(trip.times[boarded]),
+                        duration: trip.times[alighted] - trip.times[boarded],
+                    }));
+                    stop = boarded_stop;
+                    label = rounds[round].labels[stop].expect("boarded at unreached stop");
+                }
+                Parent::Walk {
+                    round,
+                    from,
+                    distance,
+                } => {
+                    let ride = rounds[round].rides[from].expect("walked from unreached stop");
+                    let start = ride.arrival;
+                    segments.push(Segment::Walk(WalkSegment {
+                        from: self.stop_point(from),
+                        to: self.stop_point(stop),
+                        start: Timestamp::from_week_seconds(start),
+                        duration: label.arrival - start,
+                        distance,
+                    }));
+                    stop = from;
+                    label = ride;
+                }
+            }
+        }
+        segments.reverse();
+        segments
+    }
+}
+
+/// Sorts trips by departure and splits them into groups in which no trip
+/// overtakes another, so that the earliest trip from each stop can be
+/// found by binary search.
+fn split_overtaking(mut trips: Vec<Trip>) -> Vec<Vec<Trip>> {
+    trips.sort_by_key(|trip| trip.times[0]);
+    let mut groups: Vec<Vec<Trip>> = Vec::new();
+    for trip in trips {
+        let group = groups.iter().position(|group| {
+            let last = &group[group.len() - 1];
+            last.times.iter().zip(&trip.times).all(|(a, b)| a <= b)
+        });
+        match group {
+            Some(group) => groups[group].push(trip),
+            None => groups.push(vec![trip]),
+        }
+    }
+    groups
+}
+
+impl Engine for Raptor {
+    /// Unlike `Searcher`, preferred lines are not taken into account.
+    fn find_route(
+        &self,
+        from: Point,
+        to: Point,
+        departure: Timestamp,
+        options: &SearchOptions,
+    ) -> Option<Route<'_>> {
+        let start = departure.week_seconds();
+        let egress = self
+            .walking
+            .within(to, options.max_walk_distance)
+            .into_iter()
+            .map(|(&index, distance)| (index, distance))
+            .collect::<Vec<_>>();
+        let rounds = self.rounds(from, start, &egress, options);
+
+        // best arrival of each round, counting boardings as penalties
+        let mut best: Option<(u64, usize, usize, f64)> = None;
+        for (round, labels) in rounds.iter().enumerate() {
+            for &(stop, distance) in &egress {
+                let label = match labels.labels[stop] {
+                    Some(label) => label,
+                    None => continue,
+                };
+                if label.walked + distance > options.max_total_walk {
+                    continue;
+                }
+                let arrival = label.arrival + options.walk_time(distance);
+                let cost = arrival + options.transfer_penalty * round as u64;
+                let is_better = match best {
+                    Some((best_arrival, best_round, _, _)) => {
+                        cost < best_arrival + options.transfer_penalty * best_round as u64
+                    }
+                    None => true,
+                };
+                if is_better {
+                    best = Some((arrival, round, stop, distance));
+                }
+            }
+        }
+        let (arrival, round, stop, distance) = best?;
+        debug!("Found route in round {}", round);
+
+        let mut segments = self.segments(&rounds, round, stop, from, departure);
+        let last_arrival = rounds[round].labels[stop]
+            .expect("final stop unreached")
+            .arrival;
+        segments.push(Segment::Walk(WalkSegment {
+            from: self.stop_point(stop),
+            to: NamedPoint {
+                id: None,
+                name: None,
+                loc: to,
+            },
+            start: Timestamp::from_week_seconds(last_arrival),
+            duration: arrival - last_arrival,
+            distance,
+        }));
+        let mut route = Route {
+            segments,
+            departure_time: departure,
+            arrival_time: departure.offset(arrival - start),
+        };
+        post_process_route(&mut route);
+        Some(route)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{assert_same_routes, engine_network, schedule, stop, track};
+    use super::super::Searcher;
+    use super::*;
+    use model::DayTime;
+
+    #[test]
+    fn same_routes_as_searcher() {
+        let (stops, schedules) = engine_network();
+        let searcher = Searcher::new(stops.clone(), schedules.clone());
+        let raptor = Raptor::new(stops, schedules);
+        assert_same_routes(&raptor, &searcher);
+    }
+
+    #[test]
+    fn catches_overtaking_trips() {
+        let stops = vec![stop("a", 54.70, 25.20), stop("c", 54.72, 25.20)];
+        let mut slow = track("A", &["a", "c"], &[DayTime::new(10, 0)], 1800);
+        let fast = track("A", &["a", "c"], &[DayTime::new(10, 5)], 300);
+        slow.timetables.extend(fast.timetables);
+        let schedules = vec![schedule("1", vec![slow])];
+        let searcher = Searcher::new(stops.clone(), schedules.clone());
+        let raptor = Raptor::new(stops, schedules);
+        assert_same_routes(&raptor, &searcher);
+    }
+}
@@ -0,0 +1,340 @@
+use super::{post_process_route, SearchOptions, Searcher};
+use model::{BusSegment, NamedPoint, Point, Route, Segment, Timestamp, WalkSegment};
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+
+// Routes are only considered if they arrive at most this long before the
+// time they are needed, in seconds.
+const MAX_LOOKBACK: u64 = 2 * 24 * 3600;
+
+struct StopInfo<'a> {
+    /// Segment leaving this stop.
+    leaving_segment: Segment<'a>,
+    /// Stop that the leaving segment goes to, `None` if it goes to the
+    /// destination.
+    child: Option<&'a str>,
+}
+
+struct HeapItem<'a> {
+    /// Seconds between leaving the stop and the requested arrival, including
+    /// transfer penalties.
+    cost: u64,
+    leave: Timestamp,
+    transfers: u64,
+    penalty: u64,
+    walked: f64,
+    stop: &'a str,
+    child: Option<&'a str>,
+    segment: Segment<'a>,
+}
+
+impl<'a> HeapItem<'a> {
+    fn new(
+        arrival: Timestamp,
+        leave: Timestamp,
+        (transfers, penalty, walked): (u64, u64, f64),
+        stop: &'a str,
+        child: Option<&'a str>,
+        segment: Segment<'a>,
+    ) -> HeapItem<'a> {
+        HeapItem {
+            cost: leave.seconds_until(arrival) + penalty,
+            leave,
+            transfers,
+            penalty,
+            walked,
+            stop,
+            child,
+            segment,
+        }
+    }
+}
+
+impl<'a> Ord for HeapItem<'a> {
+    fn cmp(&self, other: &HeapItem<'a>) -> Ordering {
+        // we want cheapest items to come first, so they must be greatest
+        self.cost.cmp(&other.cost).reverse()
+    }
+}
+
+impl<'a> PartialOrd for HeapItem<'a> {
+    fn partial_cmp(&self, other: &HeapItem<'a>) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<'a> PartialEq for HeapItem<'a> {
+    fn eq(&self, other: &HeapItem<'a>) -> bool {
+        self.cost == other.cost
+    }
+}
+
+impl<'a> Eq for HeapItem<'a> {}
+
+impl Searcher {
+    /// Finds a route that arrives at `to` no later than `arrival`, leaving
+    /// `from` as late as possible.
+    pub fn find_route_arriving_by(
+        &self,
+        from: Point,
+        to: Point,
+        arrival: Timestamp,
+        options: &SearchOptions,
+    ) -> Option<Route<'_>> {
+        let mut times = HashMap::<&str, StopInfo>::new();
+        let mut queue = BinaryHeap::new();
+        let access = self.walks(from, options.max_walk_distance);
+
+        let radius = options.max_walk_distance.min(options.max_total_walk);
+        for (id, distance) in self.walking.within(to, radius) {
+            let walk_time = options.walk_time(distance);
+            let leave = arrival.neg_offset(walk_time);
+            let segment = Segment::Walk(WalkSegment {
+                from: self.stop_point(id),
+                to: NamedPoint {
+                    id: None,
+                    name: None,
+                    loc: to,
+                },
+                start: leave,
+                duration: walk_time,
+                distance,
+            });
+            queue.push(HeapItem::new(
+                arrival,
+                leave,
+                (0, 0, distance),
+                id,
+                None,
+                segment,
+            ));
+        }
+
+        // best stop to walk to from the origin, with time of leaving origin and
+        // boarding penalties
+        let mut best: Option<(&str, Timestamp, u64)> = None;
+
+        while let Some(item) = queue.pop() {
+            if times.contains_key(item.stop) {
+                continue;
+            }
+            let stop = &self.stops[item.stop];
+            let leave = item.leave;
+            trace!(
+                "Must leave stop {} ({}) at {} ({} transfers)",
+                item.stop,
+                stop.name,
+                leave,
+                item.transfers,
+            );
+            times.insert(
+                item.stop,
+                StopInfo {
+                    leaving_segment: item.segment,
+                    child: item.child,
+                },
+            );
+
+            // we can only start by walking to a stop and boarding a bus there,
+            // or by walking past the stop to the destination
+            let boarding_time = match item.segment {
+                Segment::Bus(_) => Some(options.transfer_time),
+                Segment::Walk(_) if item.child.is_none() => Some(0),
+                Segment::Walk(_) => None,
+            };
+            let dist_from_start = access.get(item.stop).cloned();
+            if let (Some(boarding_time), Some(dist_from_start)) = (boarding_time, dist_from_start) {
+                if item.walked + dist_from_start <= options.max_total_walk {
+                    let start =
+                        leave.neg_offset(boarding_time + options.walk_time(dist_from_start));
+                    let cost = start.seconds_until(arrival) + item.penalty;
+                    let is_better = match best {
+                        Some((_, best_start, penalty)) => {
+                            cost < best_start.seconds_until(arrival) + penalty
+                        }
+                        None => true,
+                    };
+                    if is_better {
+                        best = Some((item.stop, start, item.penalty));
+                    }
+                }
+            }
+
+            // check incoming bus routes
+            for &(ref prev_stop, index) in &stop.incoming {
+                let route = &self.stops[prev_stop].routes[index];
+                if !options.allows(&route.bus, route.typ) {
+                    continue;
+                }
+                let (is_transfering, need_arrival) = match item.segment {
+                    Segment::Bus(segment) if segment.trip == route.trip => (false, leave),
+                    Segment::Bus(_) => (true, leave.neg_offset(options.transfer_time)),
+                    Segment::Walk(_) => (true, leave),
+                };
+                if is_transfering && !options.may_board(item.transfers) {
+                    continue;
+                }
+                if route.arrival.seconds_until(need_arrival) > MAX_LOOKBACK {
+                    continue;
+                }
+                let penalty = if is_transfering {
+                    item.penalty + options.boarding_penalty(&route.bus)
+                } else {
+                    item.penalty
+                };
+                let segment = Segment::Bus(BusSegment {
+                    bus: &route.bus,
+                    trip: route.trip,
+                    typ: route.typ,
+                    from: self.stop_point(prev_stop),
+                    to: self.stop_point(item.stop),
+                    start: route.departure,
+                    duration: route.duration,
+                });
+                queue.push(HeapItem::new(
+                    arrival,
+                    route.departure,
+                    (item.transfers + is_transfering as u64, penalty, item.walked),
+                    prev_stop,
+                    Some(item.stop),
+                    segment,
+                ));
+            }
+
+            // try walking from nearby stops, but only if we board a bus here
+            if let Segment::Bus(_) = item.segment {
+                let need_arrival = leave.neg_offset(options.transfer_time);
+                for &(ref id, distance) in &stop.incoming_footpaths {
+                    if distance > options.max_walk_distance
+                        || item.walked + distance > options.max_total_walk
+                    {
+                        continue;
+                    }
+                    let walk_time = options.walk_time(distance);
+                    let start = need_arrival.neg_offset(walk_time);
+                    let segment = Segment::Walk(WalkSegment {
+                        from: self.stop_point(id),
+                        to: self.stop_point(item.stop),
+                        start,
+                        duration: walk_time,
+                        distance,
+                    });
+                    queue.push(HeapItem::new(
+                        arrival,
+                        start,
+                        (item.transfers, item.penalty, item.walked + distance),
+                        id,
+                        Some(item.stop),
+                        segment,
+                    ));
+                }
+            }
+        }
+
+        let (first_stop, departure_time, _) = best?;
+        debug!("Found route, leaving at {}", departure_time);
+
+        let distance = access[first_stop];
+        let walk_time = options.walk_time(distance);
+        let mut route_segments = vec![Segment::Walk(WalkSegment {
+            from: NamedPoint {
+                id: None,
+                name: None,
+                loc: from,
+            },
+            to: self.stop_point(first_stop),
+            start: departure_time,
+            duration: walk_time,
+            distance,
+        })];
+        let mut current = Some(first_stop);
+        while let Some(stop) = current {
+            let info = times.remove(stop).unwrap();
+            route_segments.push(info.leaving_segment);
+            current = info.child;
+        }
+
+        // walks were planned to end as late as possible, but we start walking
+        // as soon as we get off the bus
+        let mut end = route_segments[0].end();
+        for segment in &mut route_segments[1..] {
+            if let Segment::Walk(ref mut walk) = *segment {
+                walk.start = end;
+            }
+            end = segment.end();
+        }
+
+        let mut route = Route {
+            segments: route_segments,
+            departure_time,
+            arrival_time: end,
+        };
+        post_process_route(&mut route);
+
+        Some(route)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{ends, schedule, stop, track};
+    use super::*;
+    use model::{Day, DayTime};
+
+    #[test]
+    fn leaves_as_late_as_possible() {
+        let stops = vec![stop("a", 54.70, 25.20), stop("b", 54.72, 25.20)];
+        let departures = [DayTime::new(7, 0), DayTime::new(7, 30), DayTime::new(8, 0)];
+        let schedules = vec![schedule(
+            "1",
+            vec![track("A", &["a", "b"], &departures, 600)],
+        )];
+        let searcher = Searcher::new(stops, schedules);
+        let (from, to) = ends();
+        let arrival = Timestamp::new(Day::Monday, DayTime::new(8, 0));
+        let route = searcher
+            .find_route_arriving_by(from, to, arrival, &SearchOptions::default())
+            .unwrap();
+
+        assert_eq!(
+            route.departure_time,
+            Timestamp::new(Day::Monday, DayTime::new(7, 27))
+        );
+        assert_eq!(
+            route.arrival_time,
+            Timestamp::new(Day::Monday, DayTime::new(7, 40))
+        );
+        match route.segments[1] {
+            Segment::Bus(bus) => assert_eq!(bus.start.time(), DayTime::new(7, 30)),
+            Segment::Walk(_) => panic!("expected bus segment"),
+        }
+    }
+
+    #[test]
+    fn walks_when_destination_is_near() {
+        let searcher = Searcher::new(vec![stop("a", 54.70, 25.20)], Vec::new());
+        let from = Point {
+            lat: 54.70,
+            lng: 25.20,
+        };
+        let to = Point {
+            lat: 54.702,
+            lng: 25.20,
+        };
+        let options = SearchOptions::default();
+        let arrival = Timestamp::new(Day::Monday, DayTime::new(8, 0));
+        let route = searcher
+            .find_route_arriving_by(from, to, arrival, &options)
+            .unwrap();
+        let forward = searcher
+            .find_route(from, to, route.departure_time, &options)
+            .unwrap();
+
+        assert_eq!(route.arrival_time, arrival);
+        assert_eq!(forward.arrival_time, arrival);
+        assert!(route.segments.iter().all(|segment| match *segment {
+            Segment::Walk(_) => true,
+            Segment::Bus(_) => false,
+        }));
+    }
+}
@@ -0,0 +1,309 @@
+use super::grid::Grid;
+use super::GRID_CELL;
+use model::{Point, Streets};
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+
+// Places farther than this from any street node, in meters, are walked to
+// in a straight line.
+const MAX_SNAP: f64 = 300.0;
+
+/// Items at known locations, usually stops, that can be found by walking
+/// distance from any place.
+#[derive(Debug, Clone)]
+pub struct Walking<T> {
+    items: Vec<(Point, T)>,
+    /// Indices of `items`, by location.
+    grid: Grid<usize>,
+    streets: Option<StreetGraph>,
+}
+
+#[derive(Debug, Clone)]
+struct StreetGraph {
+    locations: Vec<Point>,
+    /// Indices of nodes, by location.
+    nodes: Grid<usize>,
+    /// Neighbours of each node, with distances to them.
+    edges: Vec<Vec<(usize, f64)>>,
+    /// Items next to each node, with distances to them.
+    items: HashMap<usize, Vec<(usize, f64)>>,
+    /// Indices of items too far from any street, by location.
+    stranded: Grid<usize>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Visit {
+    distance: f64,
+    node: usize,
+}
+
+impl Ord for Visit {
+    fn cmp(&self, other: &Visit) -> Ordering {
+        // nearest nodes come first
+        other
+            .distance
+            .partial_cmp(&self.distance)
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+impl PartialOrd for Visit {
+    fn partial_cmp(&self, other: &Visit) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Eq for Visit {}
+
+impl<T> Walking<T> {
+    /// Indexes `items`, walking along `streets` if given.
+    pub fn new(items: Vec<(Point, T)>, streets: Option<&Streets>) -> Walking<T> {
+        let grid = Grid::new(items.iter().map(|&(loc, _)| loc).zip(0..), GRID_CELL);
+        let streets = streets.map(|streets| StreetGraph::new(streets, &items));
+        Walking {
+            items,
+            grid,
+            streets,
+        }
+    }
+
+    pub fn items(&self) -> impl Iterator<Item = &T> {
+        self.items.iter().map(|(_, item)| item)
+    }
+
+    /// Items one can reach by walking at most `radius` meters from `point`,
+    /// with walking distances to them.
+    pub fn within(&self, point: Point, radius: f64) -> Vec<(&T, f64)> {
+        self.reach(point, radius)
+            .into_iter()
+            .map(|(index, distance)| (&self.items[index].1, distance))
+            .collect()
+    }
+
+    /// For each item, indices of other items within `radius` meters walk
+    /// and walking distances to them.
+    pub fn paths(&self, radius: f64) -> Vec<Vec<(usize, f64)>> {
+        self.items
+            .iter()
+            .enumerate()
+            .map(|(index, &(loc, _))| {
+                self.reach(loc, radius)
+                    .into_iter()
+                    .filter(|&(other, _)| other != index)
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn reach(&self, point: Point, radius: f64) -> Vec<(usize, f64)> {
+        let straight = || {
+            self.grid
+                .within(point, radius)
+                .into_iter()
+                .map(|(&index, distance)| (index, distance))
+                .collect()
+        };
+        let streets = match self.streets {
+            Some(ref streets) => streets,
+            None => return straight(),
+        };
+        let (start, snap) = match streets.snap(point) {
+            Some(start) => start,
+            None => return straight(),
+        };
+
+        let mut found = HashMap::<usize, f64>::new();
+        let mut reached = |index: usize, distance: f64| {
+            let best = found.entry(index).or_insert(distance);
+            if distance < *best {
+                *best = distance;
+            }
+        };
+        for (node, distance) in streets.distances(start, snap, radius) {
+            for &(index, to_item) in streets.items.get(&node).into_iter().flatten() {
+                if distance + to_item <= radius {
+                    reached(index, distance + to_item);
+                }
+            }
+        }
+        for (&index, distance) in streets.stranded.within(point, radius) {
+            reached(index, distance);
+        }
+        let mut found = found.into_iter().collect::<Vec<_>>();
+        found.sort_by_key(|&(index, _)| index);
+        found
+    }
+
+    /// Points along streets one can reach by walking at most `radius` meters
+    /// from `point`, at most `step` meters apart. `None` if there are no
+    /// streets near `point`.
+    pub fn street_points(&self, point: Point, radius: f64, step: f64) -> Option<Vec<Point>> {
+        let streets = self.streets.as_ref()?;
+        let (start, snap) = streets.snap(point)?;
+        let mut points = vec![point];
+        for (node, distance) in streets.distances(start, snap, radius) {
+            if distance > radius {
+                continue;
+            }
+            let from = streets.locations[node];
+            points.push(from);
+            for &(next, length) in &streets.edges[node] {
+                let to = streets.locations[next];
+                // walk along the edge as far as the rest of the radius allows
+                let reach = length.min(radius - distance);
+                let steps = (reach / step).ceil() as usize;
+                points.extend((1..=steps).map(|i| {
+                    let share = (i as f64 * step).min(reach) / length;
+                    Point {
+                        lat: from.lat + (to.lat - from.lat) * share,
+                        lng: from.lng + (to.lng - from.lng) * share,
+                    }
+                }));
+            }
+        }
+        Some(points)
+    }
+}
+
+impl StreetGraph {
+    fn new<T>(streets: &Streets, items: &[(Point, T)]) -> StreetGraph {
+        let mut edges = vec![Vec::new(); streets.nodes.len()];
+        for &(a, b) in &streets.edges {
+            let length = streets.nodes[a].distance(streets.nodes[b]);
+            edges[a].push((b, length));
+            edges[b].push((a, length));
+        }
+        let mut graph = StreetGraph {
+            locations: streets.nodes.clone(),
+            nodes: Grid::new(streets.nodes.iter().cloned().zip(0..), GRID_CELL),
+            edges,
+            items: HashMap::new(),
+            stranded: Grid::new(Vec::new(), GRID_CELL),
+        };
+
+        let mut stranded = Vec::new();
+        for (index, &(loc, _)) in items.iter().enumerate() {
+            match graph.snap(loc) {
+                Some((node, distance)) => {
+                    graph.items.entry(node).or_default().push((index, distance))
+                }
+                None => stranded.push((loc, index)),
+            }
+        }
+        debug!(
+            "Snapped {} of {} stops to streets",
+            items.len() - stranded.len(),
+            items.len()
+        );
+        graph.stranded = Grid::new(stranded, GRID_CELL);
+        graph
+    }
+
+    /// Walking distances to nodes at most `radius` meters from `start`,
+    /// which is `snap` meters away from where the walk begins.
+    fn distances(&self, start: usize, snap: f64, radius: f64) -> HashMap<usize, f64> {
+        let mut distances = HashMap::new();
+        let mut queue = BinaryHeap::new();
+        queue.push(Visit {
+            distance: snap,
+            node: start,
+        });
+        while let Some(Visit { distance, node }) = queue.pop() {
+            if distances.contains_key(&node) {
+                continue;
+            }
+            distances.insert(node, distance);
+            for &(next, length) in &self.edges[node] {
+                if distance + length <= radius && !distances.contains_key(&next) {
+                    queue.push(Visit {
+                        distance: distance + length,
+                        node: next,
+                    });
+                }
+            }
+        }
+        distances
+    }
+
+    /// The node nearest to `point` and distance to it.
+    fn snap(&self, point: Point) -> Option<(usize, f64)> {
+        self.nodes
+            .within(point, MAX_SNAP)
+            .into_iter()
+            .map(|(&node, distance)| (node, distance))
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn walk_along_streets() {
+        // a river between a and b, crossed by a bridge 1 km to the east
+        let streets = Streets {
+            nodes: vec![
+                Point {
+                    lat: 54.700,
+                    lng: 25.200,
+                },
+                Point {
+                    lat: 54.700,
+                    lng: 25.2155,
+                },
+                Point {
+                    lat: 54.702,
+                    lng: 25.2155,
+                },
+                Point {
+                    lat: 54.702,
+                    lng: 25.200,
+                },
+            ],
+            edges: vec![(0, 1), (1, 2), (2, 3)],
+        };
+        let items = vec![
+            (
+                Point {
+                    lat: 54.700,
+                    lng: 25.200,
+                },
+                "a",
+            ),
+            (
+                Point {
+                    lat: 54.702,
+                    lng: 25.200,
+                },
+                "b",
+            ),
+            // far from any street
+            (
+                Point {
+                    lat: 54.7005,
+                    lng: 25.190,
+                },
+                "c",
+            ),
+        ];
+        fn names(found: Vec<(&&'static str, f64)>) -> Vec<&'static str> {
+            let mut names = found.into_iter().map(|(&name, _)| name).collect::<Vec<_>>();
+            names.sort();
+            names
+        }
+
+        let walking = Walking::new(items.clone(), None);
+        assert_eq!(
+            names(walking.within(items[0].0, 700.0)),
+            vec!["a", "b", "c"]
+        );
+
+        let walking = Walking::new(items.clone(), Some(&streets));
+        assert_eq!(names(walking.within(items[0].0, 700.0)), vec!["a", "c"]);
+        let paths = walking.paths(3000.0);
+        assert_eq!(paths[0].len(), 2);
+        let to_b = paths[0].iter().find(|&&(index, _)| index == 1).unwrap().1;
+        assert!(to_b > 2000.0 && to_b < 2300.0);
+    }
+}
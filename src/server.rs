@@ -0,0 +1,403 @@
+use model::{Day, DayTime, Point, Timestamp, TransportType};
+use search::{SearchOptions, Searcher};
+use serde_json::{self, Value};
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::panic::{self, AssertUnwindSafe};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::thread;
+use tiny_http::{Header, Method, Request, Response, Server};
+use url::form_urlencoded;
+use Res;
+
+type JsonResponse = Response<Cursor<Vec<u8>>>;
+
+// Most origin and destination pairs of a single matrix query.
+const MAX_MATRIX_PAIRS: usize = 10_000;
+// Longest departure window of a profile query, in seconds.
+const MAX_PROFILE_WINDOW: u64 = 6 * 3600;
+// Longest time budget of an isochrone query, in minutes.
+const MAX_ISOCHRONE_MINUTES: u64 = 3 * 60;
+// Largest radius of a nearby stops query, in meters.
+const MAX_RADIUS: f64 = 5000.0;
+
+/// Serves queries on `addr` using `threads` worker threads, each answering
+/// requests against the same searcher.
+pub fn serve(searcher: Searcher, addr: &str, threads: usize) -> Res {
+    let server = Arc::new(Server::http(addr).map_err(|e| e.to_string())?);
+    let searcher = Arc::new(searcher);
+    info!("Listening on http://{}", addr);
+
+    let workers = (0..threads.max(1))
+        .map(|_| {
+            let server = server.clone();
+            let searcher = searcher.clone();
+            thread::spawn(move || loop {
+                match server.recv() {
+                    Ok(request) => handle(&searcher, request),
+                    Err(e) => {
+                        // the listener is gone, so is every later request
+                        error!("Failed to receive request, stopping worker: {}", e);
+                        break;
+                    }
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    for worker in workers {
+        worker.join().map_err(|_| "server worker thread panicked")?;
+    }
+    Ok(())
+}
+
+fn handle(searcher: &Searcher, request: Request) {
+    let url = request.url().to_owned();
+    let (path, query) = match url.find('?') {
+        Some(index) => (&url[..index], &url[index + 1..]),
+        None => (&url[..], ""),
+    };
+    let params = form_urlencoded::parse(query.as_bytes())
+        .into_owned()
+        .collect::<HashMap<_, _>>();
+    debug!("{} {}", request.method(), url);
+
+    // a panicking query must not take the worker thread down with it
+    let response = panic::catch_unwind(AssertUnwindSafe(|| match (request.method(), path) {
+        (&Method::Get, "/route") => route(searcher, &params),
+        (&Method::Get, "/profile") => profile(searcher, &params),
+        (&Method::Get, "/isochrone") => isochrone(searcher, &params),
+        (&Method::Get, "/matrix") => matrix(searcher, &params),
+        (&Method::Get, "/departures") => departures(searcher, &params),
+        (&Method::Get, "/stops") => Ok(stops(searcher)),
+        (&Method::Get, "/stops/near") => stops_near(searcher, &params),
+        (&Method::Get, "/stops/search") => search_stops(searcher, &params),
+        (&Method::Get, "/lines") => Ok(lines(searcher)),
+        (&Method::Get, "/lines/tracks") => line_tracks(searcher, &params),
+        (&Method::Get, "/lines/trip") => trip(searcher, &params),
+        _ => Err((404, "not found".to_owned())),
+    }))
+    .unwrap_or_else(|_| {
+        error!("Request {} panicked", url);
+        Err((500, "internal error".to_owned()))
+    });
+    let response = match response {
+        Ok(json) => json_response(200, &json),
+        Err((status, message)) => json_response(status, &json!({ "error": message })),
+    };
+    if let Err(e) = request.respond(response) {
+        warn!("Failed to send response: {}", e);
+    }
+}
+
+type ApiResult = Result<Value, (u16, String)>;
+
+fn param<'a>(params: &'a HashMap<String, String>, name: &str) -> Result<&'a str, (u16, String)> {
+    params
+        .get(name)
+        .map(|value| value.as_str())
+        .ok_or_else(|| (400, format!("missing parameter: {}", name)))
+}
+
+fn locate(
+    searcher: &Searcher,
+    params: &HashMap<String, String>,
+    name: &str,
+) -> Result<Point, (u16, String)> {
+    let value = param(params, name)?;
+    searcher
+        .locate(value)
+        .ok_or_else(|| (400, format!("unknown location: {}", value)))
+}
+
+fn timestamp(params: &HashMap<String, String>, time: &str) -> Result<Timestamp, (u16, String)> {
+    let day = param(params, "day")?.parse::<Day>().map_err(|e| (400, e))?;
+    let time = param(params, time)?
+        .parse::<DayTime>()
+        .map_err(|e| (400, e))?;
+    Ok(Timestamp::new(day, time))
+}
+
+/// Reads optional routing parameters, using defaults for missing ones.
+fn search_options(params: &HashMap<String, String>) -> Result<SearchOptions, (u16, String)> {
+    fn number<T: FromStr>(
+        params: &HashMap<String, String>,
+        name: &str,
+    ) -> Result<Option<T>, (u16, String)> {
+        match params.get(name) {
+            Some(value) => value
+                .parse()
+                .map(Some)
+                .map_err(|_| (400, format!("invalid number for {}: {}", name, value))),
+            None => Ok(None),
+        }
+    }
+
+    let mut options = SearchOptions::default();
+    if let Some(speed) = number::<f64>(params, "walk_speed")? {
+        options.walk_speed = speed * 1000.0 / 3600.0;
+    }
+    if let Some(distance) = number(params, "max_walk")? {
+        options.max_walk_distance = distance;
+    }
+    if let Some(distance) = number(params, "max_total_walk")? {
+        options.max_total_walk = distance;
+    }
+    if let Some(time) = number(params, "transfer_time")? {
+        options.transfer_time = time;
+    }
+    if let Some(penalty) = number(params, "transfer_penalty")? {
+        options.transfer_penalty = penalty;
+    }
+    options.max_transfers = number(params, "max_transfers")?;
+    options.max_slowdown = number(params, "max_slowdown")?;
+    if let Some(modes) = params.get("modes") {
+        options.allowed_types = Some(transport_types(modes)?);
+    }
+    if let Some(modes) = params.get("exclude_modes") {
+        options.denied_types = transport_types(modes)?;
+    }
+    if let Some(lines) = params.get("ban_lines") {
+        options.banned_lines = list(lines).map(str::to_owned).collect();
+    }
+    if let Some(lines) = params.get("prefer_lines") {
+        options.preferred_lines = list(lines).map(str::to_owned).collect();
+    }
+    if let Some(penalty) = number(params, "other_line_penalty")? {
+        options.other_line_penalty = penalty;
+    }
+    options.validate().map_err(|e| (400, e))?;
+    Ok(options)
+}
+
+/// Splits a comma separated parameter value.
+fn list(value: &str) -> impl Iterator<Item = &str> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+}
+
+fn transport_types(value: &str) -> Result<Vec<TransportType>, (u16, String)> {
+    list(value)
+        .map(|typ| typ.parse().map_err(|e| (400, e)))
+        .collect()
+}
+
+fn route(searcher: &Searcher, params: &HashMap<String, String>) -> ApiResult {
+    let from = locate(searcher, params, "from")?;
+    let to = locate(searcher, params, "to")?;
+    let time = timestamp(params, "time")?;
+    let options = search_options(params)?;
+    let arrive_by = params.get("arrive_by").map(String::as_str) == Some("true");
+    let pareto = params.get("pareto").map(String::as_str) == Some("true");
+    // like the command line, these searches exclude each other
+    let modes = [arrive_by, pareto, params.contains_key("alternatives")];
+    if modes.iter().filter(|&&mode| mode).count() > 1 {
+        return Err((
+            400,
+            "arrive_by, pareto and alternatives cannot be combined".to_owned(),
+        ));
+    }
+    if pareto {
+        let routes = searcher.find_pareto_routes(from, to, time, &options);
+        return Ok(serde_json::to_value(&routes).expect("failed to serialize routes"));
+    }
+    if let Some(count) = params.get("alternatives") {
+        let count = count
+            .parse()
+            .map_err(|_| (400, format!("invalid number for alternatives: {}", count)))?;
+        let routes = searcher.find_alternatives(from, to, time, count, &options);
+        return Ok(serde_json::to_value(&routes).expect("failed to serialize routes"));
+    }
+
+    let route = if arrive_by {
+        searcher.find_route_arriving_by(from, to, time, &options)
+    } else {
+        searcher.find_route(from, to, time, &options)
+    };
+    match route {
+        Some(route) => Ok(serde_json::to_value(&route).expect("failed to serialize route")),
+        None => Err((404, "no route found".to_owned())),
+    }
+}
+
+fn profile(searcher: &Searcher, params: &HashMap<String, String>) -> ApiResult {
+    let from = locate(searcher, params, "from")?;
+    let to = locate(searcher, params, "to")?;
+    let earliest = timestamp(params, "time")?;
+    let until = timestamp(params, "until")?;
+    let window = earliest.seconds_until(until);
+    if window > MAX_PROFILE_WINDOW {
+        return Err((
+            400,
+            format!(
+                "departure window is longer than {} hours",
+                MAX_PROFILE_WINDOW / 3600
+            ),
+        ));
+    }
+    let latest = earliest.offset(window);
+
+    let options = search_options(params)?;
+    let routes = searcher.find_profile(from, to, earliest, latest, &options);
+    Ok(serde_json::to_value(&routes).expect("failed to serialize routes"))
+}
+
+fn isochrone(searcher: &Searcher, params: &HashMap<String, String>) -> ApiResult {
+    let from = locate(searcher, params, "from")?;
+    let departure = timestamp(params, "time")?;
+    let minutes = param(params, "minutes")?;
+    let budget = minutes
+        .parse::<u64>()
+        .map_err(|_| (400, format!("invalid number for minutes: {}", minutes)))?;
+    if budget > MAX_ISOCHRONE_MINUTES {
+        return Err((
+            400,
+            format!("minutes should be at most {}", MAX_ISOCHRONE_MINUTES),
+        ));
+    }
+    let options = search_options(params)?;
+    let isochrone = searcher.find_isochrone(from, departure, budget * 60, &options);
+    Ok(isochrone.geojson())
+}
+
+/// Places given as a `;` separated list, since coordinates contain commas.
+fn places<'a>(
+    searcher: &Searcher,
+    params: &'a HashMap<String, String>,
+    name: &str,
+) -> Result<Vec<(&'a str, Point)>, (u16, String)> {
+    param(params, name)?
+        .split(';')
+        .map(str::trim)
+        .map(|place| {
+            let point = searcher
+                .locate(place)
+                .ok_or_else(|| (400, format!("unknown location: {}", place)))?;
+            Ok((place, point))
+        })
+        .collect()
+}
+
+fn matrix(searcher: &Searcher, params: &HashMap<String, String>) -> ApiResult {
+    let origins = places(searcher, params, "origins")?;
+    let destinations = if params.contains_key("destinations") {
+        places(searcher, params, "destinations")?
+    } else {
+        origins.clone()
+    };
+    if origins.len() * destinations.len() > MAX_MATRIX_PAIRS {
+        return Err((
+            400,
+            format!(
+                "more than {} origin and destination pairs",
+                MAX_MATRIX_PAIRS
+            ),
+        ));
+    }
+    let departure = timestamp(params, "time")?;
+    let options = search_options(params)?;
+    let (origin_ids, origins): (Vec<_>, Vec<_>) = origins.into_iter().unzip();
+    let (destination_ids, destinations): (Vec<_>, Vec<_>) = destinations.into_iter().unzip();
+    let durations = searcher.travel_times(&origins, &destinations, departure, &options);
+    Ok(json!({
+        "origins": origin_ids,
+        "destinations": destination_ids,
+        "durations": durations,
+    }))
+}
+
+fn departures(searcher: &Searcher, params: &HashMap<String, String>) -> ApiResult {
+    let stop = param(params, "stop")?;
+    let time = timestamp(params, "time")?;
+    let count = match params.get("count") {
+        Some(count) => count
+            .parse::<usize>()
+            .map_err(|_| (400, format!("invalid number for count: {}", count)))?,
+        None => 10,
+    };
+    let departures = searcher
+        .departures(stop, time, count)
+        .ok_or_else(|| (404, format!("unknown stop: {}", stop)))?;
+    Ok(serde_json::to_value(&departures).expect("failed to serialize departures"))
+}
+
+fn stops(searcher: &Searcher) -> Value {
+    let mut stops = searcher.stops().collect::<Vec<_>>();
+    stops.sort_by_key(|stop| stop.id);
+    serde_json::to_value(&stops).expect("failed to serialize stops")
+}
+
+fn stops_near(searcher: &Searcher, params: &HashMap<String, String>) -> ApiResult {
+    let point = locate(searcher, params, "location")?;
+    let radius = match params.get("radius") {
+        Some(value) => value
+            .parse::<f64>()
+            .map_err(|_| (400, format!("invalid number for radius: {}", value)))?,
+        None => SearchOptions::default().max_walk_distance,
+    };
+    if !(0.0..=MAX_RADIUS).contains(&radius) {
+        return Err((
+            400,
+            format!("radius should be in range [0; {}]", MAX_RADIUS),
+        ));
+    }
+    let stops = searcher
+        .stops_near(point, radius)
+        .into_iter()
+        .map(|(stop, distance)| {
+            json!({
+                "id": stop.id,
+                "name": stop.name,
+                "lat": stop.loc.lat,
+                "lng": stop.loc.lng,
+                "distance": distance,
+            })
+        })
+        .collect::<Vec<_>>();
+    Ok(Value::Array(stops))
+}
+
+fn search_stops(searcher: &Searcher, params: &HashMap<String, String>) -> ApiResult {
+    let query = param(params, "q")?;
+    let limit = match params.get("limit") {
+        Some(limit) => limit
+            .parse::<usize>()
+            .map_err(|_| (400, format!("invalid number for limit: {}", limit)))?,
+        None => 10,
+    };
+    let found = searcher.find_stops(query, limit);
+    Ok(serde_json::to_value(&found).expect("failed to serialize stops"))
+}
+
+fn lines(searcher: &Searcher) -> Value {
+    serde_json::to_value(searcher.lines()).expect("failed to serialize lines")
+}
+
+fn line_tracks(searcher: &Searcher, params: &HashMap<String, String>) -> ApiResult {
+    let line = param(params, "line")?;
+    let tracks = searcher
+        .line_tracks(line)
+        .ok_or_else(|| (404, format!("unknown line: {}", line)))?;
+    Ok(serde_json::to_value(&tracks).expect("failed to serialize tracks"))
+}
+
+fn trip(searcher: &Searcher, params: &HashMap<String, String>) -> ApiResult {
+    let line = param(params, "line")?;
+    let track = param(params, "track")?;
+    let departure = timestamp(params, "time")?;
+    let trip = searcher
+        .find_trip(line, track, departure)
+        .ok_or_else(|| (404, format!("no trip of line {} track {}", line, track)))?;
+    Ok(serde_json::to_value(&trip).expect("failed to serialize trip"))
+}
+
+fn json_response(status: u16, json: &Value) -> JsonResponse {
+    let body = serde_json::to_vec(json).expect("failed to serialize json");
+    let header =
+        Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).expect("invalid header");
+    Response::from_data(body)
+        .with_status_code(status)
+        .with_header(header)
+}